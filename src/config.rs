@@ -0,0 +1,579 @@
+use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+pub const CONFIG_FILE: &str = "simple.toml";
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Project configuration, read from `simple.toml` next to `src`.
+/// Every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Write `about.html` as `about/index.html` so it's served at `/about/`.
+    pub pretty_urls: bool,
+    /// Path the site is deployed under, e.g. `/docs/`. Always starts and ends
+    /// with a `/` once loaded.
+    pub base: String,
+    /// Public URL of the site without the base path, e.g. `https://example.org`.
+    /// Required for anything that needs absolute URLs, like the sitemap.
+    pub site_url: Option<String>,
+    pub sitemap: SitemapConfig,
+    pub search: SearchConfig,
+    pub markdown: MarkdownConfig,
+    pub callouts: CalloutsConfig,
+    pub katex: KatexConfig,
+    pub highlight: HighlightConfig,
+    pub images: ImagesConfig,
+    pub check: CheckConfig,
+    pub fingerprint: FingerprintConfig,
+    pub minify: MinifyConfig,
+}
+
+/// Build-mode minification. Pages are always minified.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MinifyConfig {
+    /// Minify `.css` files from `src/public`.
+    pub css: bool,
+    /// Minify `.js` and `.mjs` files from `src/public`.
+    pub js: bool,
+    /// Minify `<style>` and `<script>` blocks in pages.
+    pub inline: bool,
+    /// Inline local `@import`s into the stylesheet that imports them.
+    pub bundle: bool,
+}
+
+impl Default for MinifyConfig {
+    fn default() -> Self {
+        MinifyConfig {
+            css: true,
+            js: true,
+            inline: true,
+            bundle: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FingerprintConfig {
+    /// Copy files from `src/public` to names with a hash of their contents,
+    /// like `styles.3f9a1c2e.css`, and point references at them.
+    pub enabled: bool,
+    /// Extensions of the files to fingerprint.
+    pub extensions: Vec<String>,
+    /// Files left as they are, as paths relative to `src/public`, file names,
+    /// or folders ending in `/`.
+    pub exclude: Vec<String>,
+    /// Path of the JSON manifest mapping original to fingerprinted paths,
+    /// relative to the output directory.
+    pub manifest: String,
+}
+
+impl Default for FingerprintConfig {
+    fn default() -> Self {
+        FingerprintConfig {
+            enabled: false,
+            extensions: [
+                "css", "js", "mjs", "png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "woff",
+                "woff2",
+            ]
+            .iter()
+            .map(|e| e.to_string())
+            .collect(),
+            exclude: [
+                "favicon.ico",
+                "robots.txt",
+                "apple-touch-icon.png",
+                "site.webmanifest",
+                "sw.js",
+            ]
+            .iter()
+            .map(|e| e.to_string())
+            .collect(),
+            manifest: "asset-manifest.json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckConfig {
+    /// Check links after every build, not just with `simple check`.
+    pub enabled: bool,
+    /// URL prefixes external links must start with. When set, any other
+    /// external link is reported; otherwise external links aren't checked.
+    pub allowlist: Option<Vec<String>>,
+    /// Internal URL prefixes that aren't checked, e.g. paths served by
+    /// something other than the site.
+    pub ignore: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Webp,
+    Avif,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    /// Process local images referenced by `<img>` tags: add their dimensions
+    /// and generate resized and converted variants.
+    pub enabled: bool,
+    /// Widths of the resized variants. Widths at or above the original's are
+    /// skipped.
+    pub widths: Vec<u32>,
+    /// Formats to convert images to, offered through a `<picture>`.
+    pub formats: Vec<ImageFormat>,
+    /// Add `loading="lazy"` to images that don't set `loading`.
+    pub lazy: bool,
+    /// Default `sizes` attribute for images with a `srcset`.
+    pub sizes: Option<String>,
+    /// Folder in the output the variants are written to.
+    pub output: String,
+    /// Folder, relative to the project, where variants are kept between
+    /// builds.
+    pub cache: String,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        ImagesConfig {
+            enabled: false,
+            widths: vec![480, 960, 1440],
+            formats: vec![ImageFormat::Webp],
+            lazy: true,
+            sizes: None,
+            output: "_images".to_string(),
+            cache: ".simple-cache/images".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlightConfig {
+    /// Syntax highlighting theme, either the name of a bundled theme or a
+    /// `.tmTheme` file relative to the project. When set, its stylesheet is
+    /// written to `output` and linked from pages with code blocks.
+    pub theme: Option<String>,
+    /// Theme used when `prefers-color-scheme` is dark.
+    pub dark_theme: Option<String>,
+    /// Path of the stylesheet, relative to the output directory.
+    pub output: String,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        HighlightConfig {
+            theme: None,
+            dark_theme: None,
+            output: "highlight.css".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KatexConfig {
+    /// KaTeX's `dist` folder, relative to the project. When set, its
+    /// stylesheet and fonts are copied into the output instead of linking to
+    /// the CDN.
+    pub path: Option<String>,
+    /// Folder in the output the KaTeX files are copied to.
+    pub output: String,
+    /// File of `\newcommand` / `\def` definitions available in all math,
+    /// relative to the project.
+    pub macros: Option<String>,
+    pub output_type: KatexOutput,
+    /// Report invalid math as a build error. When `false`, KaTeX renders the
+    /// source in `error_color` instead.
+    pub throw_on_error: bool,
+    pub error_color: Option<String>,
+    /// Allow commands like `\href` and `\includegraphics`.
+    pub trust: bool,
+    /// Left-align display math.
+    pub fleqn: bool,
+    /// Put equation numbers on the left.
+    pub leqno: bool,
+    /// Parsed from the `macros` file on load.
+    #[serde(skip)]
+    pub macro_definitions: Vec<(String, String)>,
+}
+
+impl Default for KatexConfig {
+    fn default() -> Self {
+        KatexConfig {
+            path: None,
+            output: "katex".to_string(),
+            macros: None,
+            output_type: KatexOutput::Html,
+            throw_on_error: true,
+            error_color: None,
+            trust: false,
+            fleqn: false,
+            leqno: false,
+            macro_definitions: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KatexOutput {
+    #[default]
+    Html,
+    Mathml,
+    /// HTML for display plus MathML for accessibility.
+    Both,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CalloutsConfig {
+    /// Render `> [!NOTE]` style blockquotes as callouts.
+    pub enabled: bool,
+    /// Recognised callout types, lowercase.
+    pub types: Vec<String>,
+    /// Render callouts with this component instead of the built-in markup.
+    /// It gets `type` and `title` props and the content as its slot.
+    pub component: Option<String>,
+    /// Add the bundled stylesheet to pages with callouts. Ignored when a
+    /// component is used.
+    pub stylesheet: bool,
+}
+
+impl Default for CalloutsConfig {
+    fn default() -> Self {
+        CalloutsConfig {
+            enabled: true,
+            types: ["note", "tip", "important", "warning", "caution"]
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+            component: None,
+            stylesheet: true,
+        }
+    }
+}
+
+/// Markdown options. Each rendering option can be overridden for a single
+/// block with an attribute of the same name, e.g. `<markdown smart="true">`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownConfig {
+    /// Turn straight quotes, `--` and `...` into their typographic versions.
+    pub smart: bool,
+    /// Render every newline in a paragraph as a `<br>`.
+    pub hardbreaks: bool,
+    /// Pass raw HTML in markdown through to the output.
+    pub unsafe_html: bool,
+    /// Give each heading a slugged `id`. Blocks with a `<toc />` or heading
+    /// anchors get them either way.
+    pub heading_ids: bool,
+    /// Add a `#` link to each heading pointing at its own `id`.
+    pub heading_anchors: bool,
+    pub description_lists: bool,
+    pub footnotes: bool,
+    pub tables: bool,
+    pub tasklists: bool,
+    pub strikethrough: bool,
+    pub superscript: bool,
+    pub autolink: bool,
+    /// `==marked==` text.
+    pub highlight: bool,
+    /// `$inline$` and `$$display$$` math, rendered with KaTeX.
+    pub math: bool,
+    /// Layout for markdown pages in `src/pages` whose frontmatter doesn't
+    /// name one.
+    pub layout: Option<String>,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        MarkdownConfig {
+            smart: false,
+            hardbreaks: false,
+            unsafe_html: true,
+            heading_ids: false,
+            heading_anchors: false,
+            description_lists: false,
+            footnotes: true,
+            tables: true,
+            tasklists: true,
+            strikethrough: true,
+            superscript: true,
+            autolink: true,
+            highlight: true,
+            math: true,
+            layout: None,
+        }
+    }
+}
+
+impl MarkdownConfig {
+    /// Override a single option by name, as given in a `<markdown>` attribute.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let option = match key {
+            "smart" => &mut self.smart,
+            "hardbreaks" => &mut self.hardbreaks,
+            "unsafe_html" => &mut self.unsafe_html,
+            "heading_ids" => &mut self.heading_ids,
+            "heading_anchors" => &mut self.heading_anchors,
+            "description_lists" => &mut self.description_lists,
+            "footnotes" => &mut self.footnotes,
+            "tables" => &mut self.tables,
+            "tasklists" => &mut self.tasklists,
+            "strikethrough" => &mut self.strikethrough,
+            "superscript" => &mut self.superscript,
+            "autolink" => &mut self.autolink,
+            "highlight" => &mut self.highlight,
+            "math" => &mut self.math,
+            _ => return Err(format!("Unknown markdown option `{}`", key)),
+        };
+        *option = match value {
+            "true" => true,
+            "false" => false,
+            _ => {
+                return Err(format!(
+                    "Markdown option `{}` must be \"true\" or \"false\", found \"{}\"",
+                    key, value
+                ))
+            }
+        };
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    /// Write a JSON search index of every page after each build.
+    pub enabled: bool,
+    /// Path of the index, relative to the output directory.
+    pub path: String,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            enabled: false,
+            path: "search-index.json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SitemapConfig {
+    /// Write `sitemap.xml` after each build. Only has an effect with `site_url` set.
+    pub enabled: bool,
+}
+
+impl Default for SitemapConfig {
+    fn default() -> Self {
+        SitemapConfig { enabled: true }
+    }
+}
+
+/// Load the configuration for the project in `dir`. Falls back to the
+/// defaults if there's no config file.
+pub fn load(dir: &Path) -> Result<(), ProcessError> {
+    let path = dir.join(CONFIG_FILE);
+
+    let mut config: Config = if path.exists() {
+        let content = fs::read_to_string(&path).map_proc_err(
+            WithItem::File,
+            ErrorType::Io,
+            &path,
+            Some("Failed to read config file".to_string()),
+        )?;
+        toml::from_str(&content).map_proc_err(WithItem::File, ErrorType::Syntax, &path, None)?
+    } else {
+        Config::default()
+    };
+
+    config.base = normalize_base(&config.base);
+    config.katex.output = config.katex.output.trim_matches('/').to_string();
+    config.images.output = config.images.output.trim_matches('/').to_string();
+    if let Some(macros) = &config.katex.macros {
+        let path = dir.join(macros);
+        let content = fs::read_to_string(&path).map_proc_err(
+            WithItem::File,
+            ErrorType::Io,
+            &path,
+            Some("Failed to read KaTeX macros file".to_string()),
+        )?;
+        config.katex.macro_definitions =
+            parse_macros(&content).map_err(|message| ProcessError {
+                error_type: ErrorType::Syntax,
+                item: WithItem::File,
+                path: path.clone(),
+                message: Some(message),
+            })?;
+    }
+    config.callouts.types = config
+        .callouts
+        .types
+        .iter()
+        .map(|t| t.to_ascii_lowercase())
+        .collect();
+    config.site_url = config
+        .site_url
+        .map(|url| url.trim_end_matches('/').to_string());
+
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+/// Absolute URL of the site root including the base path, e.g.
+/// `https://example.org/docs/`, if `site_url` is configured.
+pub fn site_root() -> Option<String> {
+    let config = get();
+    config
+        .site_url
+        .as_ref()
+        .map(|url| format!("{}{}", url, config.base))
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| Config {
+        base: normalize_base(""),
+        ..Config::default()
+    })
+}
+
+fn normalize_base(base: &str) -> String {
+    let trimmed = base.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        format!("/{}/", trimmed)
+    }
+}
+
+/// Parse macro definitions in LaTeX syntax, one of `\newcommand{\name}{body}`,
+/// `\newcommand{\name}[2]{body with #1 and #2}`, `\renewcommand` or
+/// `\def\name{body}`. `%` starts a comment.
+fn parse_macros(input: &str) -> Result<Vec<(String, String)>, String> {
+    let input: String = input
+        .lines()
+        .map(|line| match line.find('%') {
+            Some(i) if !line[..i].ends_with('\\') => &line[..i],
+            _ => line,
+        })
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    let mut macros = Vec::new();
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        let (name, after) = if let Some(after) = rest
+            .strip_prefix("\\newcommand")
+            .or_else(|| rest.strip_prefix("\\renewcommand"))
+        {
+            let (name, after) = take_group(after.trim_start()).ok_or_else(|| {
+                format!(
+                    "Expected {{\\name}} after \\newcommand near `{}`",
+                    snippet(after)
+                )
+            })?;
+            let after = after.trim_start();
+            // The argument count isn't needed, KaTeX works it out from the body
+            let after = match after.strip_prefix('[') {
+                Some(count) => count
+                    .split_once(']')
+                    .map(|(_, after)| after)
+                    .ok_or_else(|| format!("Unclosed argument count near `{}`", snippet(after)))?,
+                None => after,
+            };
+            (name.trim().to_string(), after)
+        } else if let Some(after) = rest.strip_prefix("\\def") {
+            let after = after.trim_start();
+            let end = after
+                .find(|c: char| c == '{' || c == '#' || c.is_whitespace())
+                .unwrap_or(after.len());
+            (after[..end].to_string(), &after[end..])
+        } else {
+            return Err(format!(
+                "Expected \\newcommand or \\def near `{}`",
+                snippet(rest)
+            ));
+        };
+
+        if !name.starts_with('\\') || name.len() < 2 {
+            return Err(format!("Invalid macro name `{}`", name));
+        }
+
+        // Skip a `\def` parameter list like `#1#2`
+        let after = after.trim_start_matches(|c: char| c == '#' || c.is_ascii_digit());
+        let (body, after) = take_group(after.trim_start())
+            .ok_or_else(|| format!("Expected {{body}} for macro `{}`", name))?;
+        macros.push((name, body.to_string()));
+        rest = after.trim_start();
+    }
+
+    Ok(macros)
+}
+
+/// Split a brace-delimited group off the start of `input`, returning its
+/// contents and the rest.
+fn take_group(input: &str) -> Option<(&str, &str)> {
+    let inner = input.strip_prefix('{')?;
+    let mut depth = 1;
+    let mut escaped = false;
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&inner[..i], &inner[i + 1..]));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn snippet(s: &str) -> &str {
+    let end = s.char_indices().nth(30).map(|(i, _)| i).unwrap_or(s.len());
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_macros() {
+        let input = r"
+% Number sets
+\newcommand{\R}{\mathbb{R}}
+\renewcommand{\vec}[1]{\mathbf{#1}} % bold vectors
+\def\abs#1{\left|#1\right|}
+";
+        let macros = parse_macros(input).unwrap();
+        assert_eq!(
+            macros,
+            vec![
+                ("\\R".to_string(), "\\mathbb{R}".to_string()),
+                ("\\vec".to_string(), "\\mathbf{#1}".to_string()),
+                ("\\abs".to_string(), "\\left|#1\\right|".to_string()),
+            ]
+        );
+
+        assert!(parse_macros("\\newcommand{\\R}{\\mathbb{R}").is_err());
+        assert!(parse_macros("\\R = 1").is_err());
+    }
+}
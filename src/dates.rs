@@ -0,0 +1,186 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// A calendar date, as used for `lastmod` and feed dates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// Parse the date formats people tend to put in frontmatter:
+    /// `2025-01-15` (optionally followed by a time), `Jan 15 2025`,
+    /// `January 15, 2025` and `15 Jan 2025`.
+    pub fn parse(input: &str) -> Option<Date> {
+        let input = input.trim();
+
+        if let Some(date) = Self::parse_iso(input) {
+            return Some(date);
+        }
+
+        let parts: Vec<&str> = input
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|p| !p.is_empty())
+            .collect();
+        if parts.len() != 3 {
+            return None;
+        }
+
+        let (month, day) = match (month_from_name(parts[0]), month_from_name(parts[1])) {
+            (Some(month), _) => (month, parts[1]),
+            (None, Some(month)) => (month, parts[0]),
+            _ => return None,
+        };
+        let day = day
+            .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+            .parse()
+            .ok()?;
+        let year = parts[2].parse().ok()?;
+
+        Self::new(year, month, day)
+    }
+
+    fn parse_iso(input: &str) -> Option<Date> {
+        let date = input.get(..10)?;
+        let mut parts = date.splitn(3, '-');
+        let year = parts.next()?;
+        let month = parts.next()?;
+        let day = parts.next()?;
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return None;
+        }
+        Self::new(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)
+    }
+
+    fn new(year: i32, month: u32, day: u32) -> Option<Date> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(Date { year, month, day })
+    }
+
+    pub fn today() -> Option<Date> {
+        Self::from_system_time(SystemTime::now())
+    }
+
+    /// Format as used by RSS, e.g. `Wed, 15 Jan 2025 00:00:00 +0000`.
+    pub fn to_rfc2822(self) -> String {
+        let weekday = WEEKDAYS[self.days().rem_euclid(7) as usize];
+        let month = MONTHS[self.month as usize - 1];
+        format!(
+            "{}, {:02} {}{} {:04} 00:00:00 +0000",
+            weekday,
+            self.day,
+            month[..1].to_ascii_uppercase(),
+            &month[1..],
+            self.year
+        )
+    }
+
+    /// Format as used by Atom, e.g. `2025-01-15T00:00:00Z`.
+    pub fn to_rfc3339(self) -> String {
+        format!("{}T00:00:00Z", self)
+    }
+
+    pub fn from_system_time(time: SystemTime) -> Option<Date> {
+        let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Some(Self::from_days((secs / 86_400) as i64))
+    }
+
+    /// Days since 1970-01-01 (Howard Hinnant's `days_from_civil`).
+    fn days(self) -> i64 {
+        let year = if self.month <= 2 {
+            self.year as i64 - 1
+        } else {
+            self.year as i64
+        };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = (self.month as i64 + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    /// Convert days since 1970-01-01 to a date (Howard Hinnant's `civil_from_days`).
+    fn from_days(days: i64) -> Date {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+        Date { year, month, day }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+fn month_from_name(name: &str) -> Option<u32> {
+    let name = name.to_ascii_lowercase();
+    let prefix = name.get(..3)?;
+    MONTHS
+        .iter()
+        .position(|m| *m == prefix)
+        .map(|i| i as u32 + 1)
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        let expected = Some(Date {
+            year: 2025,
+            month: 1,
+            day: 15,
+        });
+        assert_eq!(Date::parse("2025-01-15"), expected);
+        assert_eq!(Date::parse("2025-01-15T10:00:00Z"), expected);
+        assert_eq!(Date::parse("Jan 15 2025"), expected);
+        assert_eq!(Date::parse("January 15, 2025"), expected);
+        assert_eq!(Date::parse("15 Jan 2025"), expected);
+        assert_eq!(Date::parse("Feb 30 2025"), None);
+        assert_eq!(Date::parse("soon"), None);
+    }
+
+    #[test]
+    fn test_from_days() {
+        assert_eq!(Date::from_days(0).to_string(), "1970-01-01");
+        assert_eq!(Date::from_days(20_103).to_string(), "2025-01-15");
+        assert_eq!(Date::from_days(11_016).to_string(), "2000-02-29");
+        assert_eq!(Date::from_days(20_103).days(), 20_103);
+    }
+
+    #[test]
+    fn test_feed_formats() {
+        let date = Date::parse("Jan 15 2025").unwrap();
+        assert_eq!(date.to_rfc2822(), "Wed, 15 Jan 2025 00:00:00 +0000");
+        assert_eq!(date.to_rfc3339(), "2025-01-15T00:00:00Z");
+    }
+}
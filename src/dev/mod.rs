@@ -8,7 +8,7 @@ use simple_websockets::{Event, Message, Responder};
 use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
@@ -163,6 +163,9 @@ pub fn spawn_watcher(args: Vec<String>) {
         "<b>The websocket port for reloading is {}.</b>",
         websocket_port
     );
+    if config::get().base != "/" {
+        cprintln!("<b>The site is served under {}.</b>", config::get().base);
+    }
 
     let dist = PathBuf::from(&args[2]).join("dev");
     let src = PathBuf::from(&args[2]).join("src");
@@ -208,22 +211,98 @@ pub fn spawn_watcher(args: Vec<String>) {
 
     let preview_addr = format!("0.0.0.0:{}", preview_port);
 
+    let base = config::get().base.trim_end_matches('/').to_string();
+
     rouille::start_server(preview_addr, move |request| {
-        {
-            let dist_str = match dist.to_str() {
-                Some(s) => s,
-                None => return Response::html("500 Internal Server Error").with_status_code(500),
-            };
-            let mut response = rouille::match_assets(request, dist_str);
-            if request.url() == "/" {
-                if let Ok(f) = fs::File::open(dist.join("index").with_extension("html")) {
+        if base.is_empty() {
+            return serve(request, &dist, "");
+        }
+        // Mirror the deployed layout by serving the site under the base path
+        match request.remove_prefix(&base) {
+            Some(stripped) if stripped.url().is_empty() => {
+                Response::redirect_301(format!("{}/", base))
+            }
+            Some(stripped) if stripped.url().starts_with('/') => serve(&stripped, &dist, &base),
+            _ if request.url() == "/" => Response::redirect_302(format!("{}/", base)),
+            _ => Response::html("404 error").with_status_code(404),
+        }
+    });
+}
+
+fn serve(request: &rouille::Request, dist: &Path, base: &str) -> Response {
+    let dist_str = match dist.to_str() {
+        Some(s) => s,
+        None => return Response::html("500 Internal Server Error").with_status_code(500),
+    };
+    let mut response = rouille::match_assets(request, dist_str);
+    if !response.is_success() {
+        match index_route(&request.url(), dist, base) {
+            Some(IndexRoute::Redirect(to)) => return Response::redirect_301(to),
+            Some(IndexRoute::Serve(index)) => {
+                if let Ok(f) = fs::File::open(&index) {
                     response = Response::from_file("text/html", f);
                 }
             }
-            if response.is_success() {
-                return response;
-            }
+            None => {}
         }
-        Response::html("404 error").with_status_code(404)
-    });
+    }
+    if response.is_success() {
+        return response;
+    }
+    Response::html("404 error").with_status_code(404)
+}
+
+/// How a URL without a file of its own maps onto a folder's `index.html`.
+#[derive(Debug, PartialEq)]
+enum IndexRoute {
+    Redirect(String),
+    Serve(PathBuf),
+}
+
+/// Serve `about/index.html` for `/about/`, redirecting `/about` there. `url`
+/// is relative to `base`.
+fn index_route(url: &str, dist: &Path, base: &str) -> Option<IndexRoute> {
+    if url.contains("..") {
+        return None;
+    }
+    let index = dist.join(url.trim_start_matches('/')).join("index.html");
+    if !index.is_file() {
+        return None;
+    }
+    if !url.ends_with('/') {
+        return Some(IndexRoute::Redirect(format!("{}{}/", base, url)));
+    }
+    Some(IndexRoute::Serve(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixture;
+
+    #[test]
+    fn test_index_route() {
+        let dist = fixture(
+            "dev-routes",
+            &[
+                ("site/about/index.html", "About"),
+                ("secret/index.html", "Secret"),
+            ],
+        );
+        let site = dist.join("site");
+
+        assert_eq!(
+            index_route("/about/", &site, "/docs"),
+            Some(IndexRoute::Serve(site.join("about/index.html")))
+        );
+        assert_eq!(
+            index_route("/about", &site, "/docs"),
+            Some(IndexRoute::Redirect("/docs/about/".to_string()))
+        );
+        assert_eq!(index_route("/missing/", &site, ""), None);
+        // Nothing outside the output directory is reachable
+        assert!(site.join("../secret/index.html").is_file());
+        assert_eq!(index_route("/../secret/", &site, ""), None);
+        fs::remove_dir_all(dist).unwrap();
+    }
 }
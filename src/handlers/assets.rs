@@ -0,0 +1,56 @@
+use crate::handlers::{callouts, highlight, katex_assets};
+
+/// Page-level assets that something in a piece of rendered output depends on,
+/// like the KaTeX stylesheet for math. Returned with every `ProcessResult` and
+/// merged upwards, so each written page gets exactly what it uses.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Assets {
+    pub katex: bool,
+    pub callouts: bool,
+    /// Highlighted code blocks.
+    pub highlight: bool,
+}
+
+impl Assets {
+    pub fn merge(&mut self, other: Assets) {
+        self.katex |= other.katex;
+        self.callouts |= other.callouts;
+        self.highlight |= other.highlight;
+    }
+}
+
+/// Add the tags for `assets` to the `<head>` of `html`, or to the start if it
+/// has none.
+pub fn inject_assets(html: String, assets: Assets) -> String {
+    let mut tags = Vec::new();
+
+    // KaTeX CSS, unless disabled
+    if assets.katex && !katex_assets::is_katex_injection_disabled() {
+        katex_assets::print_katex_message();
+        katex_assets::mark_used_in_build();
+        tags.push(katex_assets::get_katex_css_tag());
+    }
+
+    if assets.callouts {
+        if let Some(style) = callouts::get_callouts_style_tag() {
+            tags.push(style);
+        }
+    }
+
+    if assets.highlight {
+        if let Some(link) = highlight::get_highlight_css_tag() {
+            tags.push(link);
+        }
+    }
+
+    if tags.is_empty() {
+        return html;
+    }
+
+    let tags = tags.join("\n");
+    if html.contains("<head>") {
+        html.replacen("<head>", &format!("<head>\n{}", tags), 1)
+    } else {
+        format!("{}\n{}", tags, html)
+    }
+}
@@ -0,0 +1,143 @@
+use crate::config;
+use crate::utils::escape_xml;
+use comrak::html::collect_text;
+use comrak::nodes::{AstNode, Node, NodeValue};
+use comrak::Arena;
+
+const CALLOUTS_CSS: &str = include_str!("./callouts.css");
+
+/// The bundled stylesheet, if it should be added to pages with callouts.
+pub fn get_callouts_style_tag() -> Option<String> {
+    let callouts = &config::get().callouts;
+    if callouts.stylesheet && callouts.component.is_none() {
+        Some(format!("<style>\n{}</style>", CALLOUTS_CSS))
+    } else {
+        None
+    }
+}
+
+pub struct Callout {
+    kind: String,
+    title: String,
+}
+
+fn open_placeholder(index: usize) -> String {
+    format!("%%simple-callout-{}%%", index)
+}
+
+fn close_placeholder(index: usize) -> String {
+    format!("%%simple-callout-end-{}%%", index)
+}
+
+fn placeholder_paragraph<'a>(arena: &'a Arena<'a>, text: String) -> Node<'a> {
+    let paragraph = arena.alloc(AstNode::from(NodeValue::Paragraph));
+    paragraph.append(arena.alloc(AstNode::from(NodeValue::Text(text.into()))));
+    paragraph
+}
+
+/// Take the `[!TYPE] Optional title` line off the start of a blockquote, if
+/// it's a callout of one of the configured types.
+fn take_marker(blockquote: Node<'_>) -> Option<Callout> {
+    let paragraph = blockquote.first_child()?;
+    if !matches!(paragraph.data().value, NodeValue::Paragraph) {
+        return None;
+    }
+
+    let mut line = Vec::new();
+    for node in paragraph.children() {
+        let end = matches!(
+            node.data().value,
+            NodeValue::SoftBreak | NodeValue::LineBreak
+        );
+        line.push(node);
+        if end {
+            break;
+        }
+    }
+    let text: String = line.iter().map(|node| collect_text(node)).collect();
+    let text = text.trim();
+
+    let rest = text.strip_prefix("[!")?;
+    let (kind, title) = rest.split_once(']')?;
+    let kind = kind.to_ascii_lowercase();
+    if !config::get().callouts.types.contains(&kind) {
+        return None;
+    }
+
+    let title = match title.trim() {
+        "" => {
+            let mut chars = kind.chars();
+            chars
+                .next()
+                .map(|c| c.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        }
+        title => title.to_string(),
+    };
+
+    for node in line {
+        node.detach();
+    }
+    if paragraph.first_child().is_none() {
+        paragraph.detach();
+    }
+
+    Some(Callout { kind, title })
+}
+
+/// Replace every `> [!TYPE]` blockquote in the document with its contents,
+/// between placeholders that `restore_callouts` swaps for the final markup.
+pub fn mark_callouts<'a>(root: Node<'a>, arena: &'a Arena<'a>) -> Vec<Callout> {
+    let blockquotes: Vec<Node<'a>> = root
+        .descendants()
+        .filter(|node| matches!(node.data().value, NodeValue::BlockQuote))
+        .collect();
+
+    let mut callouts = Vec::new();
+    for blockquote in blockquotes {
+        let Some(callout) = take_marker(blockquote) else {
+            continue;
+        };
+        let index = callouts.len();
+        callouts.push(callout);
+
+        blockquote.insert_before(placeholder_paragraph(arena, open_placeholder(index)));
+        let children: Vec<Node<'a>> = blockquote.children().collect();
+        for child in children {
+            blockquote.insert_before(child);
+        }
+        blockquote.insert_before(placeholder_paragraph(arena, close_placeholder(index)));
+        blockquote.detach();
+    }
+
+    callouts
+}
+
+pub fn restore_callouts(html: String, callouts: &[Callout]) -> String {
+    if callouts.is_empty() {
+        return html;
+    }
+
+    let component = config::get().callouts.component.as_deref();
+    let mut html = html;
+    for (index, callout) in callouts.iter().enumerate() {
+        let title = escape_xml(&callout.title);
+        let (open, close) = match component {
+            Some(name) => (
+                format!(r#"<{} type="{}" title="{}">"#, name, callout.kind, title),
+                format!("</{}>", name),
+            ),
+            None => (
+                format!(
+                    "<aside class=\"callout callout-{}\">\n<p class=\"callout-title\">{}</p>",
+                    callout.kind, title
+                ),
+                "</aside>".to_string(),
+            ),
+        };
+        html = html
+            .replace(&format!("<p>{}</p>", open_placeholder(index)), &open)
+            .replace(&format!("<p>{}</p>", close_placeholder(index)), &close);
+    }
+    html
+}
@@ -0,0 +1,115 @@
+use crate::config;
+use crate::error::{ErrorType, ProcessError, WithItem};
+use crate::handlers::outputs;
+use crate::handlers::refs::{decode_entities, html_ids, html_refs, resolve, Link};
+use crate::utils::walk_dir;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Anchor targets and references found in a generated page.
+#[derive(Debug, Default)]
+struct PageLinks {
+    ids: HashSet<String>,
+    refs: Vec<String>,
+}
+
+/// Check the `href`, `src`, `srcset` and CSS `url()` references in every
+/// page written to `dist` during the build. Internal links must lead to a file in `dist`, and
+/// their fragment to an element with that id.
+pub fn check_links(dist: &Path) -> Result<(), Vec<ProcessError>> {
+    let settings = &config::get().check;
+    let base = &config::get().base;
+    let mut errors = Vec::new();
+
+    let files = walk_dir(&dist.to_path_buf()).map_err(|e| vec![e])?;
+    let mut pages: BTreeMap<PathBuf, PageLinks> = BTreeMap::new();
+    for file in files {
+        if file.extension().and_then(|s| s.to_str()) != Some("html") {
+            continue;
+        }
+        let Ok(relative) = file.strip_prefix(dist) else {
+            continue;
+        };
+        match fs::read_to_string(&file) {
+            Ok(html) => {
+                pages.insert(relative.to_path_buf(), scan(&html));
+            }
+            Err(e) => errors.push(ProcessError {
+                error_type: ErrorType::Io,
+                item: WithItem::File,
+                path: file.clone(),
+                message: Some(format!("Failed to read page: {}", e)),
+            }),
+        }
+    }
+
+    // Only pages from this build are checked, not ones left over from earlier
+    let written: HashSet<PathBuf> = outputs::all()
+        .into_iter()
+        .filter_map(|o| o.path.strip_prefix(dist).ok().map(Path::to_path_buf))
+        .collect();
+
+    for (page, links) in pages.iter().filter(|(page, _)| written.contains(*page)) {
+        let mut seen = HashSet::new();
+        for url in &links.refs {
+            if !seen.insert(url) || settings.ignore.iter().any(|p| url.starts_with(p)) {
+                continue;
+            }
+
+            let message = match resolve(url, page, base) {
+                Link::Skipped => None,
+                Link::External => settings
+                    .allowlist
+                    .as_ref()
+                    .filter(|list| !list.iter().any(|prefix| url.starts_with(prefix)))
+                    .map(|_| format!("External link to {} isn't in the allowlist", url)),
+                Link::Outside => Some(format!("Link to {} points outside the site", url)),
+                Link::Internal(path, fragment) => match target_file(dist, &path) {
+                    None => Some(format!("Broken link to {}", url)),
+                    Some(target) => fragment
+                        .filter(|f| !f.is_empty() && f != "top")
+                        .filter(|f| pages.get(&target).is_some_and(|t| !t.ids.contains(f)))
+                        .map(|f| format!("Link to {} points to a missing anchor #{}", url, f)),
+                },
+            };
+
+            if let Some(message) = message {
+                errors.push(ProcessError {
+                    error_type: ErrorType::Other,
+                    item: WithItem::File,
+                    path: dist.join(page),
+                    message: Some(message),
+                });
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The file a link to `path` is served from, relative to the output directory.
+fn target_file(dist: &Path, path: &Path) -> Option<PathBuf> {
+    let full = dist.join(path);
+    if full.is_file() {
+        Some(path.to_path_buf())
+    } else if full.join("index.html").is_file() {
+        Some(path.join("index.html"))
+    } else {
+        None
+    }
+}
+
+fn scan(html: &str) -> PageLinks {
+    PageLinks {
+        ids: html_ids(html),
+        refs: html_refs(html)
+            .into_iter()
+            .map(|range| decode_entities(&html[range]))
+            .collect(),
+    }
+}
@@ -0,0 +1,284 @@
+use comrak::adapters::SyntaxHighlighterAdapter;
+use comrak::html::write_opening_tag;
+use comrak::plugins::syntect::SyntectAdapter;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::utils::escape_xml;
+
+/// Options from the info string of a fenced code block, after the language:
+/// ```` ```rust title="main.rs" {3-5} linenos copy ````
+#[derive(Debug, Default, PartialEq)]
+struct CodeMeta {
+    lang: String,
+    title: Option<String>,
+    /// Inclusive, 1-based line ranges to highlight.
+    lines: Vec<(usize, usize)>,
+    linenos: bool,
+    copy: bool,
+}
+
+impl CodeMeta {
+    fn parse(lang: &str, meta: &str) -> Self {
+        let mut parsed = CodeMeta {
+            lang: lang.to_string(),
+            ..CodeMeta::default()
+        };
+
+        let mut rest = meta.trim();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("title=") {
+                let (title, after) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => match after[1..].find(quote) {
+                        Some(end) => (&after[1..end + 1], &after[end + 2..]),
+                        None => (&after[1..], ""),
+                    },
+                    _ => after.split_at(after.find(char::is_whitespace).unwrap_or(after.len())),
+                };
+                parsed.title = Some(title.to_string());
+                rest = after.trim_start();
+                continue;
+            }
+
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (token, after) = rest.split_at(end);
+            match token {
+                "linenos" => parsed.linenos = true,
+                "copy" => parsed.copy = true,
+                _ => {
+                    if let Some(ranges) = token.strip_prefix('{').and_then(|t| t.strip_suffix('}'))
+                    {
+                        parsed.lines.extend(parse_ranges(ranges));
+                    }
+                }
+            }
+            rest = after.trim_start();
+        }
+
+        parsed
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.lines
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&line))
+    }
+}
+
+/// Parse `1,3-5` into `[(1, 1), (3, 5)]`, skipping anything invalid.
+fn parse_ranges(input: &str) -> Vec<(usize, usize)> {
+    input
+        .split(',')
+        .filter_map(|part| {
+            let part = part.trim();
+            match part.split_once('-') {
+                Some((start, end)) => Some((start.trim().parse().ok()?, end.trim().parse().ok()?)),
+                None => part.parse().ok().map(|n| (n, n)),
+            }
+        })
+        .collect()
+}
+
+/// Wrap each line of highlighted HTML in a `<span class="line">`. syntect's
+/// spans can cross lines, so they're closed at the end of each line and
+/// reopened on the next to keep the markup well nested.
+fn wrap_lines(html: &str, meta: &CodeMeta) -> String {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut current = String::new();
+    let mut has_text = false;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = rest.find('>').map(|i| i + 1).unwrap_or(rest.len());
+            let tag = &rest[..end];
+            if tag.starts_with("</") {
+                open.pop();
+            } else {
+                open.push(tag);
+            }
+            current.push_str(tag);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.find(['<', '\n']).unwrap_or(rest.len());
+        if end == 0 {
+            // A newline ends the line
+            current.push_str(&"</span>".repeat(open.len()));
+            lines.push(std::mem::take(&mut current));
+            current = open.concat();
+            has_text = false;
+            rest = &rest[1..];
+        } else {
+            current.push_str(&rest[..end]);
+            has_text = true;
+            rest = &rest[end..];
+        }
+    }
+    // Anything after the last newline is usually just closing tags
+    if has_text {
+        lines.push(current);
+    }
+
+    let mut out = String::with_capacity(html.len() + lines.len() * 32);
+    for (index, line) in lines.iter().enumerate() {
+        let number = index + 1;
+        out.push_str("<span class=\"line");
+        if meta.is_highlighted(number) {
+            out.push_str(" highlighted");
+        }
+        out.push('"');
+        if meta.linenos {
+            out.push_str(&format!(" data-line=\"{}\"", number));
+        }
+        out.push('>');
+        out.push_str(line);
+        out.push_str("</span>\n");
+    }
+    out
+}
+
+/// Codefence renderer that adds titles, highlighted lines, line numbers and a
+/// copy hook on top of syntect's highlighting. Holds the options of the block
+/// being rendered, so a new one is needed for each render.
+pub struct CodeBlocks<'a> {
+    syntect: &'a SyntectAdapter,
+    current: Mutex<CodeMeta>,
+}
+
+impl<'a> CodeBlocks<'a> {
+    pub fn new(syntect: &'a SyntectAdapter) -> Self {
+        CodeBlocks {
+            syntect,
+            current: Mutex::new(CodeMeta::default()),
+        }
+    }
+
+    fn with_meta<T>(&self, f: impl FnOnce(&mut CodeMeta) -> T) -> T {
+        match self.current.lock() {
+            Ok(mut meta) => f(&mut meta),
+            Err(poisoned) => f(&mut poisoned.into_inner()),
+        }
+    }
+}
+
+impl SyntaxHighlighterAdapter for CodeBlocks<'_> {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn fmt::Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> fmt::Result {
+        let mut highlighted = String::with_capacity(code.len() * 2);
+        self.syntect
+            .write_highlighted(&mut highlighted, lang, code)?;
+
+        self.with_meta(|meta| {
+            if meta.linenos || !meta.lines.is_empty() {
+                output.write_str(&wrap_lines(&highlighted, meta))
+            } else {
+                output.write_str(&highlighted)
+            }
+        })
+    }
+
+    fn write_pre_tag<'s>(
+        &self,
+        output: &mut dyn fmt::Write,
+        attributes: HashMap<&'static str, Cow<'s, str>>,
+    ) -> fmt::Result {
+        let meta = CodeMeta::parse(
+            attributes
+                .get("lang")
+                .map(|l| l.as_ref())
+                .unwrap_or_default(),
+            attributes
+                .get("data-meta")
+                .map(|m| m.as_ref())
+                .unwrap_or_default(),
+        );
+
+        if let Some(title) = &meta.title {
+            write!(
+                output,
+                "<div class=\"code-title\">{}</div>",
+                escape_xml(title)
+            )?;
+        }
+
+        let mut class = String::from("syntax-highlighting");
+        if meta.linenos {
+            class.push_str(" linenos");
+        }
+        let mut pre_attributes = vec![("class", class)];
+        if let Some(title) = &meta.title {
+            pre_attributes.push(("data-title", title.clone()));
+        }
+        if meta.copy {
+            pre_attributes.push(("data-copy", String::new()));
+        }
+        if let Some(sourcepos) = attributes.get("data-sourcepos") {
+            pre_attributes.push(("data-sourcepos", sourcepos.to_string()));
+        }
+
+        self.with_meta(|current| *current = meta);
+        write_opening_tag(output, "pre", pre_attributes)
+    }
+
+    fn write_code_tag<'s>(
+        &self,
+        output: &mut dyn fmt::Write,
+        mut attributes: HashMap<&'static str, Cow<'s, str>>,
+    ) -> fmt::Result {
+        let lang = self.with_meta(|meta| meta.lang.clone());
+        if !lang.is_empty() {
+            attributes.insert("class", format!("language-{}", lang).into());
+        }
+        write_opening_tag(output, "code", attributes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_meta() {
+        let meta = CodeMeta::parse("rust", r#"title="src/main.rs" {1,3-5} linenos copy"#);
+        assert_eq!(
+            meta,
+            CodeMeta {
+                lang: "rust".to_string(),
+                title: Some("src/main.rs".to_string()),
+                lines: vec![(1, 1), (3, 5)],
+                linenos: true,
+                copy: true,
+            }
+        );
+        assert_eq!(
+            CodeMeta::parse("js", "title=app.js").title.as_deref(),
+            Some("app.js")
+        );
+    }
+
+    #[test]
+    fn test_wrap_lines() {
+        let meta = CodeMeta {
+            lines: vec![(2, 2)],
+            linenos: true,
+            ..CodeMeta::default()
+        };
+        let html = "<span class=\"a\">one\n<span class=\"b\">two</span>\n</span>";
+        assert_eq!(
+            wrap_lines(html, &meta),
+            concat!(
+                "<span class=\"line\" data-line=\"1\"><span class=\"a\">one</span></span>\n",
+                "<span class=\"line highlighted\" data-line=\"2\"><span class=\"a\"><span class=\"b\">two</span></span></span>\n",
+            )
+        );
+    }
+}
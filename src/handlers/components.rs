@@ -1,9 +1,16 @@
 use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::assets::Assets;
+use crate::handlers::frontmatter::{has_frontmatter, parse_frontmatter};
 use crate::handlers::pages::page;
+use crate::handlers::search::search_box;
 use crate::utils::{get_inside, get_targets_kv, kv_replace, ProcessResult};
 use fancy_regex::Regex;
 use once_cell::sync::Lazy;
-use std::{collections::HashSet, fs, path::PathBuf};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
 
 static REGEX_SELF_CLOSING: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r#"(?<!<!--)<([A-Z][A-Za-z_]*(:[A-Z][A-Za-z_]*)*)(\s+[A-Za-z]+=(['\"]).*?\4)*\s*\/>(?!.*?-->)"#)
@@ -20,6 +27,24 @@ static REGEX_SLOT: Lazy<Regex> = Lazy::new(|| {
         .expect("Regex failed to parse. This shouldn't happen.")
 });
 
+/// Props take precedence over page-level variables with the same name.
+fn with_vars<'a>(
+    mut targets: Vec<(&'a str, &'a str)>,
+    vars: &[(&'a str, &'a str)],
+) -> Vec<(&'a str, &'a str)> {
+    targets.extend_from_slice(vars);
+    targets
+}
+
+/// The component's markup without the frontmatter it has if it's also a
+/// layout. Those values only apply when it's used as one.
+fn strip_frontmatter(st: String, path: &PathBuf) -> Result<String, ProcessError> {
+    if !has_frontmatter(&st) {
+        return Ok(st);
+    }
+    parse_frontmatter(&st, path).map(|fm| fm.remaining)
+}
+
 pub enum ComponentTypes {
     SelfClosing,
     Wrapping,
@@ -30,6 +55,7 @@ pub fn get_component_self(
     component: &str,
     targets: Vec<(&str, &str)>,
     mut hist: HashSet<PathBuf>,
+    vars: &[(&str, &str)],
 ) -> ProcessResult {
     let mut errors: Vec<ProcessError> = Vec::new();
     let path = src
@@ -46,6 +72,7 @@ pub fn get_component_self(
                 path,
                 message: Some(format!("{:?}", hist)),
             }],
+            assets: Assets::default(),
         };
     }
 
@@ -56,10 +83,22 @@ pub fn get_component_self(
         None,
     ) {
         Ok(content) => content,
+        // Built-in components are only used if the project doesn't define its own
+        Err(_) if component == "SearchBox" => match search_box(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                return ProcessResult {
+                    output: String::new(),
+                    errors: vec![e],
+                    assets: Assets::default(),
+                };
+            }
+        },
         Err(e) => {
             return ProcessResult {
                 output: String::new(),
                 errors: vec![e],
+                assets: Assets::default(),
             };
         }
     };
@@ -74,15 +113,28 @@ pub fn get_component_self(
         return ProcessResult {
             output: String::new(),
             errors,
+            assets: Assets::default(),
         };
     }
 
-    let st = kv_replace(targets, st);
-    let result = page(src, st, hist);
+    let st = match strip_frontmatter(st, &path) {
+        Ok(st) => st,
+        Err(e) => {
+            return ProcessResult {
+                output: String::new(),
+                errors: vec![e],
+                assets: Assets::default(),
+            };
+        }
+    };
+
+    let st = kv_replace(with_vars(targets, vars), st);
+    let result = page(src, st, hist, vars);
     errors.extend(result.errors);
     ProcessResult {
         output: result.output,
         errors,
+        assets: result.assets,
     }
 }
 
@@ -92,6 +144,7 @@ pub fn get_component_slot(
     targets: Vec<(&str, &str)>,
     slot_content: Option<String>,
     mut hist: HashSet<PathBuf>,
+    vars: &[(&str, &str)],
 ) -> ProcessResult {
     let mut errors: Vec<ProcessError> = Vec::new();
     let path = src
@@ -108,6 +161,7 @@ pub fn get_component_slot(
                 path,
                 message: Some(format!("{:?}", hist)),
             }],
+            assets: Assets::default(),
         };
     }
 
@@ -122,6 +176,7 @@ pub fn get_component_slot(
             return ProcessResult {
                 output: String::new(),
                 errors: vec![e],
+                assets: Assets::default(),
             };
         }
     };
@@ -136,9 +191,21 @@ pub fn get_component_slot(
         return ProcessResult {
             output: String::new(),
             errors,
+            assets: Assets::default(),
         };
     }
 
+    let st = match strip_frontmatter(st, &path) {
+        Ok(st) => st,
+        Err(e) => {
+            return ProcessResult {
+                output: String::new(),
+                errors: vec![e],
+                assets: Assets::default(),
+            };
+        }
+    };
+
     if !st.contains("<slot>") || !st.contains("</slot>") {
         return ProcessResult {
             output: String::new(),
@@ -150,40 +217,55 @@ pub fn get_component_slot(
                     "The component does not contain a proper <slot></slot> tag.",
                 )),
             }],
+            assets: Assets::default(),
         };
     }
 
-    let mut st = kv_replace(targets, st);
+    let mut st = kv_replace(with_vars(targets, vars), st);
     if let Some(content) = slot_content {
-        let mut result = String::with_capacity(st.len() + content.len());
-        let mut last_end = 0;
-
-        for find in REGEX_SLOT.find_iter(&st) {
-            match find {
-                Ok(mat) => {
-                    result.push_str(&st[last_end..mat.start()]);
-                    result.push_str(&content);
-                    last_end = mat.end();
-                }
-                Err(e) => {
-                    errors.push(ProcessError {
-                        error_type: ErrorType::Other,
-                        item: WithItem::Component,
-                        path: path.clone(),
-                        message: Some(format!("Regex error while processing slot tags: {}", e)),
-                    });
-                }
-            }
-        }
-        result.push_str(&st[last_end..]);
-        st = result;
+        let filled = fill_slot(&st, &content, &path);
+        errors.extend(filled.errors);
+        st = filled.output;
     }
 
-    let result = page(src, st, hist);
+    let result = page(src, st, hist, vars);
     errors.extend(result.errors);
     ProcessResult {
         output: result.output,
         errors,
+        assets: result.assets,
+    }
+}
+
+/// Replace every `<slot></slot>` in `st` with `content`.
+pub fn fill_slot(st: &str, content: &str, path: &Path) -> ProcessResult {
+    let mut errors = Vec::new();
+    let mut result = String::with_capacity(st.len() + content.len());
+    let mut last_end = 0;
+
+    for find in REGEX_SLOT.find_iter(st) {
+        match find {
+            Ok(mat) => {
+                result.push_str(&st[last_end..mat.start()]);
+                result.push_str(content);
+                last_end = mat.end();
+            }
+            Err(e) => {
+                errors.push(ProcessError {
+                    error_type: ErrorType::Other,
+                    item: WithItem::Component,
+                    path: path.to_path_buf(),
+                    message: Some(format!("Regex error while processing slot tags: {}", e)),
+                });
+            }
+        }
+    }
+    result.push_str(&st[last_end..]);
+
+    ProcessResult {
+        output: result,
+        errors,
+        assets: Assets::default(),
     }
 }
 
@@ -192,6 +274,7 @@ pub fn process_component(
     input: String,
     component_type: ComponentTypes,
     hist: HashSet<PathBuf>,
+    vars: &[(&str, &str)],
 ) -> ProcessResult {
     let regex = match component_type {
         ComponentTypes::SelfClosing => &*REGEX_SELF_CLOSING,
@@ -199,11 +282,16 @@ pub fn process_component(
     };
 
     let mut errors: Vec<ProcessError> = Vec::new();
+    let mut assets = Assets::default();
     let mut output = input;
 
     // Early return if no matches
     if !regex.is_match(&output).unwrap_or(false) {
-        return ProcessResult { output, errors };
+        return ProcessResult {
+            output,
+            errors,
+            assets: Assets::default(),
+        };
     }
 
     let mut replacements = Vec::new();
@@ -227,8 +315,9 @@ pub fn process_component(
 
                 match component_type {
                     ComponentTypes::SelfClosing => {
-                        let result = get_component_self(src, name, targets, hist.clone());
+                        let result = get_component_self(src, name, targets, hist.clone(), vars);
                         errors.extend(result.errors);
+                        assets.merge(result.assets);
                         replacements.push((found_str.to_owned(), result.output));
                     }
                     ComponentTypes::Wrapping => {
@@ -240,8 +329,10 @@ pub fn process_component(
                             targets,
                             slot_content.clone(),
                             hist.clone(),
+                            vars,
                         );
                         errors.extend(result.errors);
+                        assets.merge(result.assets);
 
                         if let Some(content) = slot_content {
                             replacements.push((content, String::new()));
@@ -266,5 +357,9 @@ pub fn process_component(
         output = output.replacen(&from, &to, 1);
     }
 
-    ProcessResult { output, errors }
+    ProcessResult {
+        output,
+        errors,
+        assets,
+    }
 }
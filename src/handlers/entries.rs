@@ -1,12 +1,21 @@
 use crate::dev::{SCRIPT, WS_PORT};
 use crate::error::{errors_to_html, ErrorType, ProcessError, WithItem};
+use crate::handlers::assets::inject_assets;
 use crate::handlers::frontmatter::extract_frontmatter;
-use crate::handlers::katex_assets;
+use crate::handlers::images::process_images;
+use crate::handlers::markdown::{rewrite_links, TOC_MARKER, TOC_SOURCE};
+use crate::handlers::minify::html_cfg;
+use crate::handlers::outputs::{self, url_for};
 use crate::handlers::pages::page;
-use crate::utils::kv_replace;
+use crate::utils::{apply_base, kv_replace, normalize_path, percent_decode, pretty_path, unindent};
 use crate::IS_DEV;
 use minify_html::minify;
-use std::{collections::HashSet, fs, path::PathBuf};
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
 
 pub fn process_entry(
     src: &PathBuf,
@@ -14,13 +23,11 @@ pub fn process_entry(
     entry_path: String,
     result_path: String,
     kv: Vec<(&str, &str)>,
+    entries: &[(PathBuf, String)],
 ) -> Vec<ProcessError> {
     let mut errors: Vec<ProcessError> = Vec::new();
     let is_dev = *IS_DEV.get().unwrap_or(&false);
 
-    // Reset KaTeX usage flag for this page
-    katex_assets::reset_katex_flag();
-
     if entry_path.is_empty() || result_path.is_empty() {
         return vec![ProcessError {
             error_type: ErrorType::Other,
@@ -32,7 +39,7 @@ pub fn process_entry(
         }];
     }
 
-    let entry_path = src.join("data").join(entry_path.trim_start_matches("/"));
+    let entry_path = entry_source(src, &entry_path);
     let frame_path = src
         .join("templates")
         .join(name.replace(":", "/"))
@@ -51,9 +58,10 @@ pub fn process_entry(
         }
     };
 
-    let result_path_buf = src_parent
-        .join(if is_dev { "dev" } else { "dist" })
-        .join(result_path.trim_start_matches("/"));
+    let out_dir = src_parent.join(if is_dev { "dev" } else { "dist" });
+    let result_path_buf = out_dir.join(pretty_path(PathBuf::from(
+        result_path.trim_start_matches("/"),
+    )));
 
     let frame_content = match fs::read_to_string(&frame_path) {
         Ok(content) => content,
@@ -86,10 +94,19 @@ pub fn process_entry(
         match extract_frontmatter(&content, &entry_path) {
             Ok(fm_result) => {
                 errors.extend(fm_result.warnings);
-                frame_content.replace(
-                    "${--content}",
-                    &("<markdown>\n".to_owned() + &fm_result.remaining + "</markdown>"),
-                )
+                let (body, link_errors) =
+                    entry_body(&fm_result.remaining, &entry_path, src, entries);
+                errors.extend(link_errors);
+                // A table of contents needs ids on the headings it links to,
+                // and is filled in once they're rendered
+                let open = if frame_content.contains("${--toc}") {
+                    format!("<markdown heading_ids=\"true\">{}\n", TOC_SOURCE)
+                } else {
+                    String::from("<markdown>\n")
+                };
+                frame_content
+                    .replace("${--toc}", TOC_MARKER)
+                    .replace("${--content}", &(open + &body + "</markdown>"))
             }
             Err(e) => {
                 // Frontmatter extraction failed — report it instead of silently using raw content
@@ -102,15 +119,19 @@ pub fn process_entry(
                         e.message.as_deref().unwrap_or("unknown error")
                     )),
                 });
-                frame_content.replace("${--content}", &content)
+                frame_content
+                    .replace("${--toc}", "")
+                    .replace("${--content}", &content)
             }
         }
     } else {
-        frame_content.replace("${--content}", &content)
+        frame_content
+            .replace("${--toc}", "")
+            .replace("${--content}", &content)
     };
-    let final_content = kv_replace(kv, processed_content);
+    let final_content = kv_replace(kv.clone(), processed_content);
 
-    let page_result = page(src, final_content, HashSet::new());
+    let page_result = page(src, final_content, HashSet::new(), &kv);
 
     errors.extend(page_result.errors);
 
@@ -135,21 +156,10 @@ pub fn process_entry(
         // In build mode, still write the (potentially degraded) output — errors are reported to console
     }
 
-    let mut s = page_result.output;
-
-    // Inject KaTeX CSS if math was rendered (unless disabled)
-    if katex_assets::was_katex_used() && !katex_assets::is_katex_injection_disabled() {
-        katex_assets::print_katex_message();
-
-        if s.contains("<head>") {
-            s = s.replace(
-                "<head>",
-                &format!("<head>\n{}", katex_assets::get_katex_css_tag()),
-            );
-        } else {
-            s = format!("{}\n{}", katex_assets::get_katex_css_tag(), s);
-        }
-    }
+    let s = inject_assets(apply_base(page_result.output), page_result.assets);
+    let images = process_images(s, &result_path_buf, src_parent, &out_dir);
+    errors.extend(images.errors);
+    let mut s = images.output;
 
     if is_dev && !s.contains("// * SCRIPT INCLUDED IN DEV MODE") {
         s = s.replace("<head>", &format!("<head>{}", SCRIPT));
@@ -160,7 +170,7 @@ pub fn process_entry(
 
     // Only write normal output if there were no errors (error page already written above)
     if errors.is_empty() {
-        let output = minify(&s.into_bytes(), &minify_html::Cfg::new());
+        let output = minify(&s.into_bytes(), &html_cfg(is_dev));
 
         if let Err(e) = fs::write(&result_path_buf, &output) {
             errors.push(ProcessError {
@@ -169,12 +179,112 @@ pub fn process_entry(
                 path: result_path_buf.clone(),
                 message: Some(format!("Failed to write result file: {}", e)),
             });
+        } else {
+            outputs::record(&out_dir, &result_path_buf, &entry_path, &kv);
         }
     }
 
     errors
 }
 
+/// Source file of the entry at `entry_path`, relative to `src/data`.
+pub fn entry_source(src: &Path, entry_path: &str) -> PathBuf {
+    normalize_path(&src.join("data").join(entry_path.trim_start_matches('/')))
+}
+
+/// The markdown of an entry, with its relative links resolved (see
+/// `resolve_link`).
+pub fn entry_body(
+    markdown: &str,
+    entry_path: &Path,
+    src: &Path,
+    entries: &[(PathBuf, String)],
+) -> (String, Vec<ProcessError>) {
+    let mut errors = Vec::new();
+    let body = rewrite_links(&unindent(markdown), |url| {
+        match resolve_link(url, entry_path, src, entries)? {
+            Ok(resolved) => Some(resolved),
+            Err(message) => {
+                errors.push(ProcessError {
+                    error_type: ErrorType::Other,
+                    item: WithItem::Data,
+                    path: entry_path.to_path_buf(),
+                    message: Some(message),
+                });
+                None
+            }
+        }
+    });
+    (body, errors)
+}
+
+/// The source file and result path of each entry in a collection, so entries
+/// can link to each other by their source files.
+pub fn collection_entries(src: &Path, items: &[Value]) -> Vec<(PathBuf, String)> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let get = |key: &str| item.get(key).and_then(Value::as_str);
+            Some((
+                entry_source(src, get("--entry-path")?),
+                get("--result-path")?.to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Where a link in the markdown entry at `entry_path` should point, or `None`
+/// for external, root-relative and same-page links, which are left alone.
+/// Other links are resolved relative to the entry and must lead to another
+/// entry of the collection (see `entries`), a page or a file in `src/public`.
+fn resolve_link(
+    url: &str,
+    entry_path: &Path,
+    src: &Path,
+    entries: &[(PathBuf, String)],
+) -> Option<Result<String, String>> {
+    let split = url.find(['?', '#']).unwrap_or(url.len());
+    let (path, suffix) = url.split_at(split);
+    if path.is_empty() || path.starts_with('/') || path.contains(':') {
+        return None;
+    }
+
+    let target = normalize_path(&entry_path.parent()?.join(percent_decode(path)));
+    let link = |relative: &Path| {
+        let url = url_for(Path::new(""), &pretty_path(relative.to_path_buf()));
+        Ok(format!("{}{}", url.replace(' ', "%20"), suffix))
+    };
+
+    if let Some((_, result_path)) = entries.iter().find(|(source, _)| *source == target) {
+        return Some(link(Path::new(result_path.trim_start_matches('/'))));
+    }
+    if !target.is_file() {
+        return Some(Err(format!("Link target {} doesn't exist", url)));
+    }
+    if let Ok(relative) = target.strip_prefix(normalize_path(&src.join("public"))) {
+        return Some(Ok(format!(
+            "/{}{}",
+            relative
+                .to_string_lossy()
+                .replace('\\', "/")
+                .replace(' ', "%20"),
+            suffix
+        )));
+    }
+    if let Ok(relative) = target.strip_prefix(normalize_path(&src.join("pages"))) {
+        let relative = match relative.extension().and_then(|s| s.to_str()) {
+            Some("md") => relative.with_extension("html"),
+            _ => relative.to_path_buf(),
+        };
+        return Some(link(&relative));
+    }
+
+    Some(Err(format!(
+        "Link to {} isn't an entry of this collection, a page or a file in src/public",
+        url
+    )))
+}
+
 fn make_dev_script() -> Option<String> {
     let ws_port = WS_PORT.get()?;
     Some(SCRIPT.replace("__SIMPLE_WS_PORT_PLACEHOLDER__", &ws_port.to_string()))
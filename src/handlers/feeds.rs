@@ -0,0 +1,399 @@
+use crate::config;
+use crate::dates::Date;
+use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::entries::{collection_entries, entry_body};
+use crate::handlers::frontmatter::{
+    extract_frontmatter, load_frontmatter_data, FeedConfig, FeedContent, FileList,
+};
+use crate::handlers::markdown::render_markdown;
+use crate::handlers::outputs::url_for;
+use crate::handlers::refs::{html_refs, replace_ranges};
+use crate::utils::{apply_base, escape_xml, fill_base, kv_replace, pretty_path, walk_dir};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A collection item, ready to be written to a feed.
+struct FeedItem {
+    title: String,
+    link: String,
+    date: Option<Date>,
+    author: Option<String>,
+    content: String,
+}
+
+/// Write RSS and Atom feeds for every collection whose `Name.data.toml` has a
+/// `[feed]` table.
+pub fn write_feeds(src: &Path, dist: &Path) -> Result<(), Vec<ProcessError>> {
+    let data_dir = src.join("data");
+    if !data_dir.exists() {
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+
+    for toml_path in walk_dir(&data_dir).map_err(|e| vec![e])? {
+        let name = match toml_path
+            .strip_prefix(&data_dir)
+            .ok()
+            .and_then(|p| p.to_str())
+            .and_then(|p| p.strip_suffix(".data.toml"))
+        {
+            Some(name) => name.replace(['/', '\\'], ":"),
+            None => continue,
+        };
+
+        let feed = match read_feed_config(&toml_path) {
+            Ok(Some(feed)) => feed,
+            Ok(None) => continue,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+
+        if let Err(mut e) = write_feed(src, dist, &name, &feed, &toml_path) {
+            errors.append(&mut e);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn read_feed_config(toml_path: &PathBuf) -> Result<Option<FeedConfig>, ProcessError> {
+    let content = fs::read_to_string(toml_path).map_proc_err(
+        WithItem::Data,
+        ErrorType::Io,
+        toml_path,
+        Some("Failed to read data.toml file".to_string()),
+    )?;
+    let file_list: FileList = toml::from_str(&content).map_proc_err(
+        WithItem::Data,
+        ErrorType::Syntax,
+        toml_path,
+        None,
+    )?;
+    Ok(file_list.feed)
+}
+
+fn write_feed(
+    src: &Path,
+    dist: &Path,
+    name: &str,
+    feed: &FeedConfig,
+    toml_path: &Path,
+) -> Result<(), Vec<ProcessError>> {
+    let root = match feed
+        .site_url
+        .as_ref()
+        .map(|url| format!("{}{}", url.trim_end_matches('/'), config::get().base))
+        .or_else(config::site_root)
+    {
+        Some(root) => root,
+        None => {
+            return Err(vec![ProcessError {
+                error_type: ErrorType::Other,
+                item: WithItem::Data,
+                path: toml_path.to_path_buf(),
+                message: Some(
+                    "Feeds need absolute URLs. Set site_url in simple.toml or in [feed]."
+                        .to_string(),
+                ),
+            }]);
+        }
+    };
+
+    // Non-fatal problems with the entries are already reported when the
+    // collection is used by a template, so only hard failures are returned.
+    let (value, _) = load_frontmatter_data(&src.to_path_buf(), name)?;
+    let objects = value.as_array().cloned().unwrap_or_default();
+    let limit = feed.limit.unwrap_or(objects.len());
+    let entries = collection_entries(src, &objects);
+
+    let mut errors = Vec::new();
+    let mut items = Vec::new();
+    for obj in objects.iter().filter_map(|v| v.as_object()).take(limit) {
+        match feed_item(src, &root, feed, obj, &entries) {
+            Ok(item) => items.push(item),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    let rss_path = dist.join(&feed.rss);
+    let atom_path = dist.join(&feed.atom);
+    for (path, xml) in [
+        (&rss_path, rss(&root, feed, &items)),
+        (&atom_path, atom(&root, feed, &items)),
+    ] {
+        if let Some(parent) = path.parent() {
+            if let Err(e) = fs::create_dir_all(parent).map_proc_err(
+                WithItem::File,
+                ErrorType::Io,
+                &parent.to_path_buf(),
+                None,
+            ) {
+                errors.push(e);
+                continue;
+            }
+        }
+        if let Err(e) = fs::write(path, xml).map_proc_err(WithItem::File, ErrorType::Io, path, None)
+        {
+            errors.push(e);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn feed_item(
+    src: &Path,
+    root: &str,
+    feed: &FeedConfig,
+    obj: &Map<String, Value>,
+    entries: &[(PathBuf, String)],
+) -> Result<FeedItem, ProcessError> {
+    let get = |key: &str| obj.get(key).and_then(|v| v.as_str()).map(str::to_string);
+
+    let entry_path = src
+        .join("data")
+        .join(get("--entry-path").unwrap_or_default());
+    let result_path = get("--result-path").unwrap_or_default();
+    let url = url_for(Path::new(""), &pretty_path(PathBuf::from(&result_path)));
+    let link = format!("{}{}", root, url.trim_start_matches('/'));
+
+    let description = get("description");
+    let content = match (&feed.content, description) {
+        (FeedContent::Excerpt, Some(description)) => escape_xml(&description),
+        (content, _) => {
+            let raw = fs::read_to_string(&entry_path).map_proc_err(
+                WithItem::Data,
+                ErrorType::Io,
+                &entry_path,
+                Some("Failed to read markdown file".to_string()),
+            )?;
+            let body = extract_frontmatter(&raw, &entry_path)?.remaining;
+
+            // Links and variables come out like on the entry page, but only
+            // the markdown is rendered: expanding templates here would write
+            // entry pages again. Problems are reported when the page is built.
+            let (body, _) = entry_body(&body, &entry_path, src, entries);
+            let vars: Vec<(&str, &str)> = obj
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str(), v.as_str()?)))
+                .collect();
+            let markdown = kv_replace(vars, format!("<markdown>\n{}</markdown>", body));
+            let rendered = render_markdown(fill_base(markdown));
+            let html = absolute_urls(&apply_base(rendered.output), &root_origin(root), &link);
+            if *content == FeedContent::Full {
+                html
+            } else {
+                first_paragraph(&html)
+            }
+        }
+    };
+
+    Ok(FeedItem {
+        title: get("title").unwrap_or_default(),
+        link,
+        date: get("date").as_deref().and_then(Date::parse),
+        author: get("author"),
+        content,
+    })
+}
+
+/// `https://example.org` for a root of `https://example.org/docs/`.
+fn root_origin(root: &str) -> String {
+    let host_start = root.find("://").map_or(0, |i| i + 3);
+    match root[host_start..].find('/') {
+        Some(end) => root[..host_start + end].to_string(),
+        None => root.to_string(),
+    }
+}
+
+/// Make the URLs in `html`, an entry at `page`, absolute so feed readers
+/// don't need to resolve them.
+fn absolute_urls(html: &str, origin: &str, page: &str) -> String {
+    let page = page.split(['?', '#']).next().unwrap_or_default();
+    let page_dir = &page[..page.rfind('/').map_or(page.len(), |i| i + 1)];
+    let replacements = html_refs(html)
+        .into_iter()
+        .filter_map(|range| {
+            let url = &html[range.clone()];
+            let has_scheme = url
+                .split(['/', '?', '#'])
+                .next()
+                .is_some_and(|s| s.contains(':'));
+            let absolute = if url.starts_with("//") || has_scheme {
+                return None;
+            } else if url.starts_with('/') {
+                format!("{}{}", origin, url)
+            } else if url.starts_with(['#', '?']) {
+                format!("{}{}", page, url)
+            } else {
+                format!("{}{}", page_dir, url)
+            };
+            Some((range, absolute))
+        })
+        .collect();
+    replace_ranges(html, replacements)
+}
+
+fn first_paragraph(html: &str) -> String {
+    match (html.find("<p>"), html.find("</p>")) {
+        (Some(start), Some(end)) if start < end => html[start..end + 4].to_string(),
+        _ => String::new(),
+    }
+}
+
+fn rss(root: &str, feed: &FeedConfig, items: &[FeedItem]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n",
+    );
+    xml.push_str(&format!("<title>{}</title>\n", escape_xml(&feed.title)));
+    xml.push_str(&format!("<link>{}</link>\n", escape_xml(root)));
+    xml.push_str(&format!(
+        "<description>{}</description>\n",
+        escape_xml(feed.description.as_deref().unwrap_or(&feed.title))
+    ));
+    xml.push_str(&format!(
+        "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+        escape_xml(&format!("{}{}", root, feed.rss))
+    ));
+
+    for item in items {
+        xml.push_str("<item>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape_xml(&item.title)));
+        xml.push_str(&format!("<link>{}</link>\n", escape_xml(&item.link)));
+        xml.push_str(&format!(
+            "<guid isPermaLink=\"true\">{}</guid>\n",
+            escape_xml(&item.link)
+        ));
+        if let Some(date) = item.date {
+            xml.push_str(&format!("<pubDate>{}</pubDate>\n", date.to_rfc2822()));
+        }
+        if let Some(author) = &item.author {
+            xml.push_str(&format!(
+                "<dc:creator xmlns:dc=\"http://purl.org/dc/elements/1.1/\">{}</dc:creator>\n",
+                escape_xml(author)
+            ));
+        }
+        xml.push_str(&format!(
+            "<description>{}</description>\n",
+            escape_xml(&item.content)
+        ));
+        xml.push_str("</item>\n");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn atom(root: &str, feed: &FeedConfig, items: &[FeedItem]) -> String {
+    // Atom requires an updated timestamp on the feed and on every entry
+    let fallback = Date::today().map(Date::to_rfc3339).unwrap_or_default();
+    let updated = items
+        .iter()
+        .filter_map(|item| item.date)
+        .max()
+        .map(Date::to_rfc3339)
+        .unwrap_or_else(|| fallback.clone());
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+    );
+    xml.push_str(&format!("<title>{}</title>\n", escape_xml(&feed.title)));
+    if let Some(description) = &feed.description {
+        xml.push_str(&format!(
+            "<subtitle>{}</subtitle>\n",
+            escape_xml(description)
+        ));
+    }
+    xml.push_str(&format!("<id>{}</id>\n", escape_xml(root)));
+    xml.push_str(&format!("<link href=\"{}\"/>\n", escape_xml(root)));
+    xml.push_str(&format!(
+        "<link href=\"{}\" rel=\"self\"/>\n",
+        escape_xml(&format!("{}{}", root, feed.atom))
+    ));
+    xml.push_str(&format!("<updated>{}</updated>\n", updated));
+    if let Some(author) = &feed.author {
+        xml.push_str(&format!(
+            "<author><name>{}</name></author>\n",
+            escape_xml(author)
+        ));
+    }
+
+    for item in items {
+        xml.push_str("<entry>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape_xml(&item.title)));
+        xml.push_str(&format!("<id>{}</id>\n", escape_xml(&item.link)));
+        xml.push_str(&format!("<link href=\"{}\"/>\n", escape_xml(&item.link)));
+        xml.push_str(&format!(
+            "<updated>{}</updated>\n",
+            item.date
+                .map(Date::to_rfc3339)
+                .unwrap_or_else(|| fallback.clone())
+        ));
+        if let Some(author) = &item.author {
+            xml.push_str(&format!(
+                "<author><name>{}</name></author>\n",
+                escape_xml(author)
+            ));
+        }
+        let tag = if feed.content == FeedContent::Full {
+            "content"
+        } else {
+            "summary"
+        };
+        xml.push_str(&format!(
+            "<{tag} type=\"html\">{}</{tag}>\n",
+            escape_xml(&item.content)
+        ));
+        xml.push_str("</entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_absolute_urls() {
+        let html = concat!(
+            r#"<a href="/docs/about/">About</a><a href="../other/">Other</a>"#,
+            r##"<a href="#setup">Setup</a><a href="https://example.com">Ext</a>"##,
+            r#"<img src="/docs/img/a.png" srcset="/docs/img/a-480.png 480w">"#,
+        );
+        assert_eq!(
+            root_origin("https://example.org/docs/"),
+            "https://example.org"
+        );
+        assert_eq!(
+            absolute_urls(
+                html,
+                "https://example.org",
+                "https://example.org/docs/content/post/"
+            ),
+            concat!(
+                r#"<a href="https://example.org/docs/about/">About</a>"#,
+                r#"<a href="https://example.org/docs/content/post/../other/">Other</a>"#,
+                r##"<a href="https://example.org/docs/content/post/#setup">Setup</a>"##,
+                r#"<a href="https://example.com">Ext</a>"#,
+                r#"<img src="https://example.org/docs/img/a.png" "#,
+                r#"srcset="https://example.org/docs/img/a-480.png 480w">"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::config;
+use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::outputs;
+use crate::handlers::refs::{
+    css_refs, decode_entities, html_refs, js_refs, replace_ranges, resolve, Link,
+};
+use crate::utils::{fnv1a, walk_dir};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Content hashes of the assets copied from `src/public`, computed on demand
+/// so a stylesheet's hash covers the hashed names of the files it references.
+struct Fingerprints<'a> {
+    /// Output directory, where the copies are read from after minification.
+    dist: &'a Path,
+    base: &'a str,
+    /// Paths relative to `src/public` of the files to fingerprint.
+    candidates: HashSet<PathBuf>,
+    /// Hash and final contents of each file hashed so far.
+    hashed: HashMap<PathBuf, (String, Vec<u8>)>,
+    /// Files being hashed, to break reference cycles.
+    visiting: HashSet<PathBuf>,
+    errors: Vec<ProcessError>,
+}
+
+impl Fingerprints<'_> {
+    /// Hash of the asset at `path`, or `None` if it isn't fingerprinted.
+    fn hash(&mut self, path: &Path) -> Option<String> {
+        if let Some((hash, _)) = self.hashed.get(path) {
+            return Some(hash.clone());
+        }
+        if !self.candidates.contains(path) || !self.visiting.insert(path.to_path_buf()) {
+            return None;
+        }
+
+        let file = self.dist.join(path);
+        let contents =
+            match fs::read(&file).map_proc_err(WithItem::File, ErrorType::Io, &file, None) {
+                Ok(bytes) => self.rewrite_asset(path, bytes),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.visiting.remove(path);
+                    return None;
+                }
+            };
+        self.visiting.remove(path);
+
+        let hash = format!("{:08x}", fnv1a(&contents) as u32);
+        self.hashed
+            .insert(path.to_path_buf(), (hash.clone(), contents));
+        Some(hash)
+    }
+
+    /// Point the references in a stylesheet or script at fingerprinted files.
+    fn rewrite_asset(&mut self, path: &Path, bytes: Vec<u8>) -> Vec<u8> {
+        let find_refs: fn(&str) -> Vec<Range<usize>> =
+            match path.extension().and_then(|s| s.to_str()) {
+                Some("css") => css_refs,
+                Some("js") | Some("mjs") => js_refs,
+                _ => return bytes,
+            };
+        match String::from_utf8(bytes) {
+            Ok(text) => {
+                let refs = find_refs(&text);
+                self.rewrite(&text, refs, path).into_bytes()
+            }
+            Err(e) => e.into_bytes(),
+        }
+    }
+
+    /// Replace the `refs` in `text`, a file at `from` relative to the output
+    /// directory, that point at fingerprinted files.
+    fn rewrite(&mut self, text: &str, refs: Vec<Range<usize>>, from: &Path) -> String {
+        let mut replacements = Vec::new();
+        for range in refs {
+            let url = decode_entities(&text[range.clone()]);
+            let Link::Internal(target, _) = resolve(&url, from, self.base) else {
+                continue;
+            };
+            if let Some(hash) = self.hash(&target) {
+                let replacement = with_hash(&text[range.clone()], &hash);
+                replacements.push((range, replacement));
+            }
+        }
+        replace_ranges(text, replacements)
+    }
+}
+
+/// Copy the assets in `public` to fingerprinted names in `dist`, point the
+/// pages written during the build at them and write the manifest. The
+/// original files are left in place for anything that isn't rewritten, like
+/// feeds and links from other sites.
+pub fn fingerprint_assets(public: &Path, dist: &Path) -> Result<(), Vec<ProcessError>> {
+    let settings = &config::get().fingerprint;
+    if !settings.enabled || !public.exists() {
+        return Ok(());
+    }
+
+    let files = walk_dir(&public.to_path_buf()).map_err(|e| vec![e])?;
+    let mut candidates: Vec<PathBuf> = files
+        .iter()
+        .filter_map(|file| file.strip_prefix(public).ok())
+        .filter(|path| {
+            path.extension()
+                .and_then(|s| s.to_str())
+                .is_some_and(|ext| {
+                    settings
+                        .extensions
+                        .iter()
+                        .any(|e| e.eq_ignore_ascii_case(ext))
+                })
+                && !is_excluded(path, &settings.exclude)
+        })
+        .map(Path::to_path_buf)
+        .collect();
+    candidates.sort();
+
+    let mut fingerprints = Fingerprints {
+        dist,
+        base: &config::get().base,
+        candidates: candidates.iter().cloned().collect(),
+        hashed: HashMap::new(),
+        visiting: HashSet::new(),
+        errors: Vec::new(),
+    };
+    for path in &candidates {
+        fingerprints.hash(path);
+    }
+
+    for output in outputs::all() {
+        let Ok(page) = output.path.strip_prefix(dist) else {
+            continue;
+        };
+        let html = match fs::read_to_string(&output.path).map_proc_err(
+            WithItem::File,
+            ErrorType::Io,
+            &output.path,
+            None,
+        ) {
+            Ok(html) => html,
+            Err(e) => {
+                fingerprints.errors.push(e);
+                continue;
+            }
+        };
+        let rewritten = fingerprints.rewrite(&html, html_refs(&html), page);
+        if rewritten != html {
+            if let Err(e) = fs::write(&output.path, rewritten).map_proc_err(
+                WithItem::File,
+                ErrorType::Io,
+                &output.path,
+                None,
+            ) {
+                fingerprints.errors.push(e);
+            }
+        }
+    }
+
+    let mut errors = fingerprints.errors;
+    let mut manifest = BTreeMap::new();
+    for (path, (hash, contents)) in &fingerprints.hashed {
+        let hashed = hashed_path(path, hash);
+        let out = dist.join(&hashed);
+        if let Err(e) =
+            fs::write(&out, contents).map_proc_err(WithItem::File, ErrorType::Io, &out, None)
+        {
+            errors.push(e);
+        }
+        manifest.insert(url_path(path), url_path(&hashed));
+    }
+
+    let manifest_path = dist.join(&settings.manifest);
+    let json = serde_json::to_string_pretty(&manifest).unwrap_or_default();
+    if let Err(e) = fs::write(&manifest_path, json).map_proc_err(
+        WithItem::File,
+        ErrorType::Io,
+        &manifest_path,
+        None,
+    ) {
+        errors.push(e);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Whether `path` (relative to `src/public`) matches an exclude pattern: a
+/// relative path, a file name, or a folder ending in `/`.
+fn is_excluded(path: &Path, exclude: &[String]) -> bool {
+    let relative = url_path(path);
+    let name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    exclude.iter().any(|pattern| {
+        let pattern = pattern.trim_start_matches('/');
+        if pattern.ends_with('/') {
+            relative.starts_with(pattern)
+        } else {
+            relative == pattern || name == pattern
+        }
+    })
+}
+
+fn url_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// `css/styles.css` becomes `css/styles.<hash>.css`.
+fn hashed_path(path: &Path, hash: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    path.with_file_name(insert_hash(name, hash))
+}
+
+/// Point `url` at the fingerprinted file, keeping its query and fragment.
+fn with_hash(url: &str, hash: &str) -> String {
+    let path_end = url.find(['?', '#']).unwrap_or(url.len());
+    let name_start = url[..path_end].rfind('/').map_or(0, |i| i + 1);
+    format!(
+        "{}{}{}",
+        &url[..name_start],
+        insert_hash(&url[name_start..path_end], hash),
+        &url[path_end..]
+    )
+}
+
+fn insert_hash(name: &str, hash: &str) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}.{}{}", &name[..dot], hash, &name[dot..]),
+        _ => format!("{}.{}", name, hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_hash() {
+        assert_eq!(
+            with_hash("/css/styles.css", "3f9a1c2e"),
+            "/css/styles.3f9a1c2e.css"
+        );
+        assert_eq!(
+            with_hash("../img/a%20b.min.png?v=1#x", "3f9a1c2e"),
+            "../img/a%20b.min.3f9a1c2e.png?v=1#x"
+        );
+        assert_eq!(
+            hashed_path(Path::new("js/app.js"), "3f9a1c2e"),
+            PathBuf::from("js/app.3f9a1c2e.js")
+        );
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let exclude = [
+            "favicon.ico".to_string(),
+            "/static/".to_string(),
+            "js/sw.js".to_string(),
+        ];
+        assert!(is_excluded(Path::new("favicon.ico"), &exclude));
+        assert!(is_excluded(Path::new("icons/favicon.ico"), &exclude));
+        assert!(is_excluded(Path::new("static/logo.png"), &exclude));
+        assert!(is_excluded(Path::new("js/sw.js"), &exclude));
+        assert!(!is_excluded(Path::new("sw.js"), &exclude));
+        assert!(!is_excluded(Path::new("styles.css"), &exclude));
+    }
+}
@@ -1,4 +1,6 @@
 use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::templates::is_template_name;
+use crate::utils::page_link;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
@@ -8,21 +10,102 @@ use std::path::PathBuf;
 #[derive(Debug, Deserialize, Serialize)]
 pub struct FileList {
     pub files: Vec<String>,
+    pub feed: Option<FeedConfig>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedContent {
+    /// The whole rendered entry.
+    Full,
+    /// The `description` field, or the first paragraph of the entry.
+    #[default]
+    Excerpt,
+}
+
+/// The `[feed]` table of a `Name.data.toml` file.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedConfig {
+    pub title: String,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    /// Overrides the project's `site_url`.
+    pub site_url: Option<String>,
+    /// Maximum number of items, counted from the top of `files`.
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub content: FeedContent,
+    /// Output paths, relative to the output directory.
+    #[serde(default = "default_rss_path")]
+    pub rss: String,
+    #[serde(default = "default_atom_path")]
+    pub atom: String,
+}
+
+fn default_rss_path() -> String {
+    "feed.xml".to_string()
+}
+
+fn default_atom_path() -> String {
+    "atom.xml".to_string()
 }
 
 /// Result of frontmatter extraction: the key-value map, remaining content, and any warnings.
 pub struct FrontmatterResult {
     pub map: HashMap<String, String>,
+    /// Lists named like a template, used as its inline data. Only pages have these.
+    pub data: Vec<(String, Value)>,
     pub remaining: String,
     pub warnings: Vec<ProcessError>,
 }
 
+/// Whether the content begins with a `---` frontmatter block.
+pub fn has_frontmatter(content: &str) -> bool {
+    content.trim_start().starts_with("---")
+}
+
 /// Extract YAML frontmatter from markdown content.
 /// Returns the frontmatter map, remaining content, and any non-fatal warnings
 /// (e.g. unsupported value types that were skipped).
 pub fn extract_frontmatter(
     content: &str,
     path: &PathBuf,
+) -> Result<FrontmatterResult, ProcessError> {
+    let result = parse_frontmatter(content, path)?;
+
+    // Validate that title exists
+    if !result.map.contains_key("title") {
+        return Err(ProcessError {
+            error_type: ErrorType::Syntax,
+            item: WithItem::Data,
+            path: path.clone(),
+            message: Some("Frontmatter must contain a 'title' field".to_string()),
+        });
+    }
+
+    Ok(result)
+}
+
+/// Parse a YAML frontmatter block without requiring any particular fields.
+/// Used for layouts, where every field is optional.
+pub fn parse_frontmatter(content: &str, path: &PathBuf) -> Result<FrontmatterResult, ProcessError> {
+    parse_frontmatter_with(content, path, false)
+}
+
+/// Like [`parse_frontmatter`], but lists under keys named like a template
+/// (`Features:`) are returned as inline data for that template.
+pub fn parse_page_frontmatter(
+    content: &str,
+    path: &PathBuf,
+) -> Result<FrontmatterResult, ProcessError> {
+    parse_frontmatter_with(content, path, true)
+}
+
+fn parse_frontmatter_with(
+    content: &str,
+    path: &PathBuf,
+    allow_data: bool,
 ) -> Result<FrontmatterResult, ProcessError> {
     let content = content.trim_start();
 
@@ -51,6 +134,7 @@ pub fn extract_frontmatter(
             })?;
 
         let mut map = HashMap::new();
+        let mut data = Vec::new();
         let mut warnings = Vec::new();
 
         if let serde_yaml::Value::Mapping(mapping) = yaml_value {
@@ -72,6 +156,21 @@ pub fn extract_frontmatter(
                             });
                             continue;
                         }
+                        serde_yaml::Value::Sequence(_) if allow_data && is_template_name(k) => {
+                            match serde_json::to_value(&value) {
+                                Ok(value) => data.push((k.clone(), value)),
+                                Err(e) => warnings.push(ProcessError {
+                                    error_type: ErrorType::Syntax,
+                                    item: WithItem::Data,
+                                    path: path.clone(),
+                                    message: Some(format!(
+                                        "Frontmatter data '{}' couldn't be read: {}",
+                                        k, e
+                                    )),
+                                }),
+                            }
+                            continue;
+                        }
                         other => {
                             let type_name = match other {
                                 serde_yaml::Value::Sequence(_) => "array",
@@ -97,18 +196,9 @@ pub fn extract_frontmatter(
             }
         }
 
-        // Validate that title exists
-        if !map.contains_key("title") {
-            return Err(ProcessError {
-                error_type: ErrorType::Syntax,
-                item: WithItem::Data,
-                path: path.clone(),
-                message: Some("Frontmatter must contain a 'title' field".to_string()),
-            });
-        }
-
         Ok(FrontmatterResult {
             map,
+            data,
             remaining: remaining.to_string(),
             warnings,
         })
@@ -199,7 +289,7 @@ pub fn load_frontmatter_data(
         // Add the special fields
         frontmatter.insert("--entry-path".to_string(), relative_entry_path);
         frontmatter.insert("--result-path".to_string(), result_path.clone());
-        frontmatter.insert("link".to_string(), format!("./{}", result_path));
+        frontmatter.insert("link".to_string(), page_link(&result_path));
 
         // Convert HashMap to serde_json::Value
         let obj: serde_json::Map<String, Value> = frontmatter
@@ -212,3 +302,49 @@ pub fn load_frontmatter_data(
 
     Ok((Value::Array(items), errors))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> PathBuf {
+        PathBuf::from("about.html")
+    }
+
+    #[test]
+    fn test_parse_frontmatter() {
+        let content = "\n---\ntitle: About\norder: 2\ndraft: false\n---\n\n<h1>${title}</h1>\n";
+        let result = parse_frontmatter(content, &path()).unwrap();
+        assert_eq!(result.map["title"], "About");
+        assert_eq!(result.map["order"], "2");
+        assert_eq!(result.map["draft"], "false");
+        assert_eq!(result.remaining, "<h1>${title}</h1>\n");
+        assert!(result.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_frontmatter_skips_unsupported_values() {
+        let content = "---\ntitle: About\nsubtitle:\ntags: [a, b]\nmeta:\n  x: 1\n---\nBody";
+        let result = parse_frontmatter(content, &path()).unwrap();
+        assert_eq!(result.map.len(), 1);
+        let mut messages: Vec<String> = result
+            .warnings
+            .into_iter()
+            .filter_map(|w| w.message)
+            .collect();
+        messages.sort();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].contains("'meta' has an unsupported type (nested mapping)"));
+        assert!(messages[1].contains("'subtitle' has a null value"));
+        assert!(messages[2].contains("'tags' has an unsupported type (array)"));
+    }
+
+    #[test]
+    fn test_parse_frontmatter_errors() {
+        assert!(parse_frontmatter("---\ntitle: About\n<h1></h1>", &path()).is_err());
+        assert!(parse_frontmatter("---\ntitle: [\n---\n", &path()).is_err());
+        // Entries need a title, pages don't
+        assert!(extract_frontmatter("---\ndate: 2025-01-15\n---\n", &path()).is_err());
+        assert!(parse_frontmatter("---\ndate: 2025-01-15\n---\n", &path()).is_ok());
+    }
+}
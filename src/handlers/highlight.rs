@@ -0,0 +1,194 @@
+use crate::config;
+use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use std::fs;
+use std::path::{Path, PathBuf};
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{css_for_theme_with_class_style, ClassStyle};
+
+/// Selector of the highlighted code blocks the stylesheet applies to.
+const SCOPE: &str = "pre.syntax-highlighting";
+
+/// Load a theme by the name of one of syntect's bundled themes, or from a
+/// `.tmTheme` file relative to `dir`.
+fn load_theme(theme: &str, dir: &Path) -> Result<Theme, ProcessError> {
+    let path = dir.join(theme);
+    if theme.ends_with(".tmTheme") || path.is_file() {
+        return ThemeSet::get_theme(&path).map_err(|e| ProcessError {
+            error_type: ErrorType::Syntax,
+            item: WithItem::File,
+            path: path.clone(),
+            message: Some(format!("Failed to load theme: {}", e)),
+        });
+    }
+
+    let mut defaults = ThemeSet::load_defaults();
+    defaults.themes.remove(theme).ok_or_else(|| {
+        let mut names: Vec<&String> = defaults.themes.keys().collect();
+        names.sort();
+        ProcessError {
+            error_type: ErrorType::Other,
+            item: WithItem::None,
+            path: PathBuf::from(theme),
+            message: Some(format!(
+                "Unknown theme. Use a path to a .tmTheme file or one of: {}",
+                names
+                    .iter()
+                    .map(|n| n.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )),
+        }
+    })
+}
+
+fn theme_css(theme: &str, dir: &Path) -> Result<String, ProcessError> {
+    let loaded = load_theme(theme, dir)?;
+    // Must match the class style comrak's syntect adapter emits
+    css_for_theme_with_class_style(&loaded, ClassStyle::Spaced)
+        .map(|css| scope_rules(&css))
+        .map_err(|e| ProcessError {
+            error_type: ErrorType::Other,
+            item: WithItem::None,
+            path: PathBuf::from(theme),
+            message: Some(format!("Failed to generate CSS for theme: {}", e)),
+        })
+}
+
+/// Put every rule of a syntect stylesheet under [`SCOPE`]. The token classes
+/// are plain words like `.string` and `.comment`, which would otherwise style
+/// anything on the page that happens to use them. The theme's `.code` rule
+/// becomes the rule for the block itself.
+fn scope_rules(css: &str) -> String {
+    let mut out = String::with_capacity(css.len() * 2);
+    for line in css.split_inclusive('\n') {
+        let selectors = match line.trim_end().strip_suffix('{') {
+            Some(selectors) if !line.starts_with(char::is_whitespace) => selectors,
+            _ => {
+                out.push_str(line);
+                continue;
+            }
+        };
+        let scoped: Vec<String> = selectors
+            .split(',')
+            .map(|selector| match selector.trim() {
+                ".code" => SCOPE.to_string(),
+                selector => format!("{} {}", SCOPE, selector),
+            })
+            .collect();
+        out.push_str(&scoped.join(", "));
+        out.push_str(" {\n");
+    }
+    out
+}
+
+/// Stylesheet for the highlighting classes in markdown code blocks. With a
+/// `dark` theme, its rules apply when `prefers-color-scheme` is dark.
+pub fn highlight_css(light: &str, dark: Option<&str>, dir: &Path) -> Result<String, ProcessError> {
+    let mut css = theme_css(light, dir)?;
+    if let Some(dark) = dark {
+        let dark_css = theme_css(dark, dir)?;
+        css.push_str("\n@media (prefers-color-scheme: dark) {\n");
+        for line in dark_css.lines() {
+            if !line.is_empty() {
+                css.push_str("  ");
+            }
+            css.push_str(line);
+            css.push('\n');
+        }
+        css.push_str("}\n");
+    }
+    Ok(css)
+}
+
+/// `simple highlight-css --theme <name|path> [--dark <name|path>]`: print the
+/// stylesheet for a theme.
+pub fn highlight_css_command(args: &[String]) -> Result<String, ProcessError> {
+    let mut theme = None;
+    let mut dark = None;
+    let mut rest = args.iter().skip(2);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--theme" => theme = rest.next(),
+            "--dark" => dark = rest.next(),
+            _ => {
+                return Err(ProcessError {
+                    error_type: ErrorType::Other,
+                    item: WithItem::None,
+                    path: PathBuf::new(),
+                    message: Some(format!("Unknown argument `{}`", arg)),
+                })
+            }
+        }
+    }
+
+    let Some(theme) = theme else {
+        return Err(ProcessError {
+            error_type: ErrorType::Other,
+            item: WithItem::None,
+            path: PathBuf::new(),
+            message: Some(
+                "Usage: simple highlight-css --theme <name|path> [--dark <name|path>]".to_string(),
+            ),
+        });
+    };
+
+    highlight_css(theme, dark.map(|d| d.as_str()), Path::new("."))
+}
+
+/// Write the configured theme's stylesheet into the output.
+pub fn write_highlight_css(dir: &Path, dist: &Path) -> Result<(), ProcessError> {
+    let highlight = &config::get().highlight;
+    let Some(theme) = &highlight.theme else {
+        return Ok(());
+    };
+
+    let css = highlight_css(theme, highlight.dark_theme.as_deref(), dir)?;
+    let path = dist.join(&highlight.output);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_proc_err(WithItem::File, ErrorType::Io, &path, None)?;
+    }
+    fs::write(&path, css).map_proc_err(WithItem::File, ErrorType::Io, &path, None)
+}
+
+/// Link to the generated stylesheet, if a theme is configured.
+pub fn get_highlight_css_tag() -> Option<String> {
+    let highlight = &config::get().highlight;
+    highlight.theme.as_ref().map(|_| {
+        format!(
+            r#"<link rel="stylesheet" href="{}{}">"#,
+            config::get().base,
+            highlight.output
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_css() {
+        let dir = Path::new(".");
+        let css = highlight_css("InspiredGitHub", Some("base16-ocean.dark"), dir).unwrap();
+        let (light, dark) = css
+            .split_once("@media (prefers-color-scheme: dark) {\n")
+            .unwrap();
+
+        // Token classes as comrak's syntect adapter emits them, in each theme's colors
+        assert!(light.contains("pre.syntax-highlighting {\n color: #323232;"));
+        assert!(light.contains("pre.syntax-highlighting .comment {\n color: #969896;"));
+        assert!(light.contains("pre.syntax-highlighting .keyword {\n color: #a71d5d;"));
+        assert!(dark.contains("  pre.syntax-highlighting .keyword {\n   color: #b48ead;\n  }"));
+        assert!(dark.contains(concat!(
+            "  pre.syntax-highlighting .comment, ",
+            "pre.syntax-highlighting .punctuation.definition.comment {\n   color: #65737e;"
+        )));
+        // Nothing outside highlighted blocks is styled
+        assert!(css
+            .lines()
+            .filter(|line| line.ends_with('{') && !line.starts_with("@media"))
+            .all(|line| line.trim_start().starts_with("pre.syntax-highlighting")));
+        assert!(dark.trim_end().ends_with('}'));
+        assert!(highlight_css("No Such Theme", None, dir).is_err());
+    }
+}
@@ -0,0 +1,682 @@
+use crate::config::{self, ImageFormat as OutputFormat, ImagesConfig};
+use crate::error::{ErrorType, ProcessError, WithItem};
+use crate::handlers::assets::Assets;
+use crate::handlers::refs::raw_text_ranges;
+use crate::utils::{fnv1a, normalize_path, percent_decode, ProcessResult};
+use fancy_regex::Regex;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+static IMG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"<img\b[^>]*>").unwrap());
+
+static ATTR_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"([^\s"'<>/=]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#).unwrap()
+});
+
+/// A generated copy of an image.
+#[derive(Clone, Debug)]
+struct Variant {
+    url: String,
+    width: u32,
+}
+
+/// What's known about a local image after processing it.
+#[derive(Clone, Debug)]
+struct Processed {
+    width: u32,
+    height: u32,
+    /// Smaller copies in the original format, narrowest first.
+    resized: Vec<Variant>,
+    /// Copies in each configured format, including one at the original width.
+    converted: Vec<(OutputFormat, Vec<Variant>)>,
+}
+
+type Cell = Arc<OnceCell<Result<Processed, ProcessError>>>;
+
+// Pages are written in parallel and many of them share images, so each image
+// is processed once per build and the result shared.
+static PROCESSED: Lazy<Mutex<HashMap<PathBuf, Cell>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// File names of the variants used this build, in the cache and in the output.
+#[derive(Default)]
+struct Used {
+    cached: HashSet<String>,
+    published: HashSet<String>,
+}
+
+static USED: Lazy<Mutex<Used>> = Lazy::new(|| Mutex::new(Used::default()));
+
+pub fn reset() {
+    if let Ok(mut processed) = PROCESSED.lock() {
+        processed.clear();
+    }
+    if let Ok(mut used) = USED.lock() {
+        *used = Used::default();
+    }
+}
+
+/// Remove the variants no page used this build from the cache and the output,
+/// so copies of replaced or deleted images don't pile up. Only call it after a
+/// build without errors, since a page that failed may not have had its images
+/// processed.
+pub fn prune(dir: &Path, out_dir: &Path) -> Result<(), ProcessError> {
+    let settings = &config::get().images;
+    if !settings.enabled {
+        return Ok(());
+    }
+    let Ok(used) = USED.lock() else {
+        return Ok(());
+    };
+    remove_unused(&dir.join(&settings.cache), &used.cached)?;
+    remove_unused(&out_dir.join(&settings.output), &used.published)
+}
+
+/// Delete the files directly in `folder` whose names aren't in `keep`.
+fn remove_unused(folder: &Path, keep: &HashSet<String>) -> Result<(), ProcessError> {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return Ok(());
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        if !path.is_file() || keep.contains(name.to_string_lossy().as_ref()) {
+            continue;
+        }
+        fs::remove_file(&path).map_err(|e| ProcessError {
+            error_type: ErrorType::Io,
+            item: WithItem::File,
+            path: path.clone(),
+            message: Some(format!("Failed to remove unused image: {e}")),
+        })?;
+    }
+    Ok(())
+}
+
+fn record_used(name: &str, published: bool) {
+    if let Ok(mut used) = USED.lock() {
+        let names = if published {
+            &mut used.published
+        } else {
+            &mut used.cached
+        };
+        names.insert(name.to_string());
+    }
+}
+
+/// Add dimensions, lazy loading and responsive variants to the `<img>` tags in
+/// `html` that point into `src/public`. `page` is the path the page is written
+/// to inside the output directory `out_dir`, used to resolve relative sources.
+/// Tags inside scripts and styles are left alone.
+pub fn process_images(html: String, page: &Path, dir: &Path, out_dir: &Path) -> ProcessResult {
+    process_images_with(html, page, dir, out_dir, &config::get().images)
+}
+
+fn process_images_with(
+    html: String,
+    page: &Path,
+    dir: &Path,
+    out_dir: &Path,
+    settings: &ImagesConfig,
+) -> ProcessResult {
+    let mut errors = Vec::new();
+
+    if !settings.enabled || !html.contains("<img") {
+        return ProcessResult {
+            output: html,
+            errors,
+            assets: Assets::default(),
+        };
+    }
+
+    let base = &config::get().base;
+    let public = dir.join("src").join("public");
+    let page_dir = page
+        .parent()
+        .and_then(|p| p.strip_prefix(out_dir).ok())
+        .unwrap_or(Path::new(""));
+
+    let raw_text = raw_text_ranges(&html);
+    let mut output = String::with_capacity(html.len());
+    let mut last_end = 0;
+
+    for m in IMG_REGEX.find_iter(&html) {
+        let Ok(m) = m else { continue };
+        if raw_text.iter().any(|r| r.contains(&m.start())) {
+            continue;
+        }
+        let mut attrs = parse_attrs(m.as_str());
+
+        let Some(rel) = get_attr(&attrs, "src").and_then(|src| resolve_src(src, page_dir, base))
+        else {
+            continue;
+        };
+        let file = public.join(rel);
+        if source_format(&file).is_none() || !file.is_file() {
+            continue;
+        }
+
+        let image = match processed(&file, dir, out_dir, settings) {
+            Ok(image) => image,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+
+        output.push_str(&html[last_end..m.start()]);
+        output.push_str(&render_tag(
+            &mut attrs,
+            &image,
+            in_picture(&html, m.start(), &raw_text),
+            settings,
+        ));
+        last_end = m.end();
+    }
+    output.push_str(&html[last_end..]);
+
+    ProcessResult {
+        output,
+        errors,
+        assets: Assets::default(),
+    }
+}
+
+fn processed(
+    file: &Path,
+    dir: &Path,
+    out_dir: &Path,
+    settings: &ImagesConfig,
+) -> Result<Processed, ProcessError> {
+    let cell = match PROCESSED.lock() {
+        Ok(mut processed) => processed.entry(file.to_path_buf()).or_default().clone(),
+        Err(_) => Arc::new(OnceCell::new()),
+    };
+    cell.get_or_init(|| process_image(file, dir, out_dir, settings))
+        .clone()
+}
+
+/// Read the dimensions of `file` and write its variants to the output,
+/// encoding only those that aren't in the cache yet.
+fn process_image(
+    file: &Path,
+    dir: &Path,
+    out_dir: &Path,
+    settings: &ImagesConfig,
+) -> Result<Processed, ProcessError> {
+    let err = |message: String| ProcessError {
+        error_type: ErrorType::Io,
+        item: WithItem::File,
+        path: file.to_path_buf(),
+        message: Some(message),
+    };
+
+    let format = source_format(file).ok_or_else(|| err("Unsupported image format".into()))?;
+    let bytes = fs::read(file).map_err(|e| err(format!("Failed to read image: {e}")))?;
+    let (width, height) = ImageReader::with_format(Cursor::new(&bytes), format)
+        .into_dimensions()
+        .map_err(|e| err(format!("Failed to read image dimensions: {e}")))?;
+
+    let stem: String = file
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("image")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let prefix = format!("{}-{:08x}", stem, fnv1a(&bytes) as u32);
+
+    let mut widths: Vec<u32> = settings
+        .widths
+        .iter()
+        .copied()
+        .filter(|w| *w > 0 && *w < width)
+        .collect();
+    widths.sort_unstable();
+    widths.dedup();
+
+    let cache_dir = dir.join(&settings.cache);
+    let images_dir = out_dir.join(&settings.output);
+    for d in [&cache_dir, &images_dir] {
+        fs::create_dir_all(d).map_err(|e| err(format!("Failed to create {}: {e}", d.display())))?;
+    }
+
+    let decoded: OnceCell<DynamicImage> = OnceCell::new();
+    // Encode a copy into the cache, returning its file name and size
+    let encode_variant = |w: u32, target: ImageFormat| -> Result<(String, u64), ProcessError> {
+        let ext = target.extensions_str()[0];
+        let name = format!("{prefix}-{w}.{ext}");
+        let cached = cache_dir.join(&name);
+
+        if !cached.exists() {
+            let img = decoded.get_or_try_init(|| {
+                image::load_from_memory_with_format(&bytes, format)
+                    .map_err(|e| err(format!("Failed to decode image: {e}")))
+            })?;
+            let resized;
+            let img = if w < width {
+                let h = ((height as u64 * w as u64) / width as u64).max(1) as u32;
+                resized = img.resize_exact(w, h, FilterType::Lanczos3);
+                &resized
+            } else {
+                img
+            };
+            let encoded =
+                encode(img, target).map_err(|e| err(format!("Failed to encode {name}: {e}")))?;
+            fs::write(&cached, encoded)
+                .map_err(|e| err(format!("Failed to write {}: {e}", cached.display())))?;
+        }
+        record_used(&name, false);
+
+        let size = fs::metadata(&cached)
+            .map_err(|e| err(format!("Failed to read {}: {e}", cached.display())))?
+            .len();
+        Ok((name, size))
+    };
+    let publish = |name: &str, w: u32| -> Result<Variant, ProcessError> {
+        fs::copy(cache_dir.join(name), images_dir.join(name))
+            .map_err(|e| err(format!("Failed to copy {name} to the output: {e}")))?;
+        record_used(name, true);
+        Ok(Variant {
+            url: format!("{}{}/{}", config::get().base, settings.output, name),
+            width: w,
+        })
+    };
+
+    // A resized copy that's heavier than the original isn't worth offering
+    let original_size = bytes.len() as u64;
+    let mut resized = Vec::new();
+    let mut resized_sizes = Vec::new();
+    for w in &widths {
+        let (name, size) = encode_variant(*w, format)?;
+        if size <= original_size {
+            resized.push(publish(&name, *w)?);
+            resized_sizes.push((*w, size));
+        }
+    }
+
+    let mut converted = Vec::new();
+    for target in &settings.formats {
+        let target_format = match target {
+            OutputFormat::Webp => ImageFormat::WebP,
+            OutputFormat::Avif => ImageFormat::Avif,
+        };
+        // The resized copies already cover it
+        if target_format == format {
+            continue;
+        }
+        // WebP copies are encoded lossless, which only pays off for sources
+        // that are lossless themselves
+        if target_format == ImageFormat::WebP && format != ImageFormat::Png {
+            continue;
+        }
+
+        // Each copy is compared with the one it stands in for in the
+        // `<img>` srcset, and dropped if it's larger
+        let mut encoded = Vec::new();
+        for w in widths.iter().chain(std::iter::once(&width)) {
+            let (name, size) = encode_variant(*w, target_format)?;
+            let replaces = resized_sizes
+                .iter()
+                .find(|(rw, _)| rw == w)
+                .map_or(original_size, |(_, s)| *s);
+            if size <= replaces {
+                encoded.push((name, *w));
+            }
+        }
+        // Without the full size copy the format can't be offered
+        if encoded.last().map(|(_, w)| *w) != Some(width) {
+            continue;
+        }
+        let variants = encoded
+            .iter()
+            .map(|(name, w)| publish(name, *w))
+            .collect::<Result<Vec<_>, _>>()?;
+        converted.push((*target, variants));
+    }
+
+    Ok(Processed {
+        width,
+        height,
+        resized,
+        converted,
+    })
+}
+
+fn encode(img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, image::ImageError> {
+    let mut out = Cursor::new(Vec::new());
+    match format {
+        ImageFormat::Avif => DynamicImage::ImageRgba8(img.to_rgba8())
+            .write_with_encoder(AvifEncoder::new_with_speed_quality(&mut out, 8, 70))?,
+        ImageFormat::WebP => DynamicImage::ImageRgba8(img.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(&mut out))?,
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8()).write_to(&mut out, format)?,
+        _ => img.write_to(&mut out, format)?,
+    }
+    Ok(out.into_inner())
+}
+
+/// Formats images can be read from.
+fn source_format(file: &Path) -> Option<ImageFormat> {
+    match ImageFormat::from_path(file).ok()? {
+        f @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP) => Some(f),
+        _ => None,
+    }
+}
+
+/// Path of a local image relative to the output root, or `None` for external
+/// and data URLs. `page_dir` is the page's folder relative to the output root,
+/// and `base` the configured base path that root-relative URLs start with.
+fn resolve_src(src: &str, page_dir: &Path, base: &str) -> Option<PathBuf> {
+    let path = src.split(['?', '#']).next()?;
+    if path.is_empty() || path.starts_with("//") || path.contains(':') {
+        return None;
+    }
+
+    let path = percent_decode(path);
+    let joined = if path.starts_with('/') {
+        PathBuf::from(path.strip_prefix(base)?)
+    } else {
+        page_dir.join(&path)
+    };
+
+    let normalized = normalize_path(&joined);
+    match normalized.components().next() {
+        Some(Component::Normal(_)) => Some(normalized),
+        _ => None,
+    }
+}
+
+/// Attributes of an HTML tag in order, with values as written.
+fn parse_attrs(tag: &str) -> Vec<(String, Option<String>)> {
+    let inner = tag
+        .strip_prefix("<img")
+        .unwrap_or(tag)
+        .trim_end_matches('>')
+        .trim_end_matches('/');
+
+    ATTR_REGEX
+        .captures_iter(inner)
+        .filter_map(|caps| caps.ok())
+        .map(|caps| {
+            let value = caps
+                .get(2)
+                .or_else(|| caps.get(3))
+                .or_else(|| caps.get(4))
+                .map(|v| v.as_str().to_string());
+            (caps[1].to_ascii_lowercase(), value)
+        })
+        .collect()
+}
+
+fn get_attr<'a>(attrs: &'a [(String, Option<String>)], name: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(k, _)| k == name)
+        .and_then(|(_, v)| v.as_deref())
+}
+
+fn has_attr(attrs: &[(String, Option<String>)], name: &str) -> bool {
+    attrs.iter().any(|(k, _)| k == name)
+}
+
+/// Whether the tag at `pos` in `html` is inside a `<picture>`, not counting
+/// anything in the scripts and styles at `raw_text`.
+fn in_picture(html: &str, pos: usize, raw_text: &[Range<usize>]) -> bool {
+    let last = |tag: &str| {
+        html[..pos]
+            .rmatch_indices(tag)
+            .map(|(i, _)| i)
+            .find(|i| !raw_text.iter().any(|r| r.contains(i)))
+    };
+    match (last("<picture"), last("</picture")) {
+        (Some(open), Some(close)) => open > close,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+fn srcset(variants: &[Variant]) -> String {
+    variants
+        .iter()
+        .map(|v| format!("{} {}w", v.url, v.width))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The new `<img>` tag, wrapped in a `<picture>` offering the converted
+/// formats unless it's already in one or sets its own `srcset`.
+fn render_tag(
+    attrs: &mut Vec<(String, Option<String>)>,
+    image: &Processed,
+    in_picture: bool,
+    settings: &ImagesConfig,
+) -> String {
+    let set = |attrs: &mut Vec<(String, Option<String>)>, name: &str, value: String| {
+        attrs.push((name.to_string(), Some(value.replace('"', "&quot;"))));
+    };
+
+    if !has_attr(attrs, "width") && !has_attr(attrs, "height") {
+        set(attrs, "width", image.width.to_string());
+        set(attrs, "height", image.height.to_string());
+    }
+    if settings.lazy && !has_attr(attrs, "loading") {
+        set(attrs, "loading", "lazy".to_string());
+    }
+
+    let responsive = !has_attr(attrs, "srcset");
+    let src = get_attr(attrs, "src").unwrap_or_default().to_string();
+    let full = Variant {
+        url: src,
+        width: image.width,
+    };
+    if responsive && !image.resized.is_empty() {
+        let mut variants = image.resized.clone();
+        variants.push(full);
+        set(attrs, "srcset", srcset(&variants));
+    }
+    if responsive && !has_attr(attrs, "sizes") {
+        if let Some(sizes) = &settings.sizes {
+            set(attrs, "sizes", sizes.clone());
+        }
+    }
+
+    let mut img = String::from("<img");
+    for (name, value) in attrs.iter() {
+        img.push(' ');
+        img.push_str(name);
+        if let Some(value) = value {
+            img.push_str(&format!("=\"{}\"", value.replace('"', "&quot;")));
+        }
+    }
+    img.push('>');
+
+    if !responsive || in_picture || image.converted.is_empty() {
+        return img;
+    }
+
+    let sizes = get_attr(attrs, "sizes")
+        .map(|s| format!(" sizes=\"{}\"", s.replace('"', "&quot;")))
+        .unwrap_or_default();
+    let mut picture = String::from("<picture>");
+    for (format, variants) in &image.converted {
+        let mime = match format {
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Avif => "image/avif",
+        };
+        picture.push_str(&format!(
+            "<source type=\"{}\" srcset=\"{}\"{}>",
+            mime,
+            srcset(variants),
+            sizes
+        ));
+    }
+    picture.push_str(&img);
+    picture.push_str("</picture>");
+    picture
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixture;
+
+    fn variant(url: &str, width: u32) -> Variant {
+        Variant {
+            url: url.to_string(),
+            width,
+        }
+    }
+
+    #[test]
+    fn test_parse_attrs() {
+        let attrs = parse_attrs(r#"<img src="/a.png" alt='A "cat"' hidden data-x=1 />"#);
+        assert_eq!(
+            attrs,
+            vec![
+                ("src".to_string(), Some("/a.png".to_string())),
+                ("alt".to_string(), Some("A \"cat\"".to_string())),
+                ("hidden".to_string(), None),
+                ("data-x".to_string(), Some("1".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_src() {
+        let page = Path::new("blog/post");
+        assert_eq!(
+            resolve_src("/img/a.png", page, "/"),
+            Some(PathBuf::from("img/a.png"))
+        );
+        assert_eq!(
+            resolve_src("/docs/img/a.png?v=2", page, "/docs/"),
+            Some(PathBuf::from("img/a.png"))
+        );
+        assert_eq!(
+            resolve_src("../../img/a.png", page, "/"),
+            Some(PathBuf::from("img/a.png"))
+        );
+        assert_eq!(
+            resolve_src("./a.png", page, "/"),
+            Some(PathBuf::from("blog/post/a.png"))
+        );
+        assert_eq!(
+            resolve_src("/img/hero%20shot.png", page, "/"),
+            Some(PathBuf::from("img/hero shot.png"))
+        );
+        assert_eq!(resolve_src("../../../a.png", page, "/"), None);
+        assert_eq!(resolve_src("https://example.org/a.png", page, "/"), None);
+        assert_eq!(resolve_src("//cdn.example.org/a.png", page, "/"), None);
+        assert_eq!(resolve_src("data:image/png;base64,AA", page, "/"), None);
+    }
+
+    #[test]
+    fn test_render_tag() {
+        let image = Processed {
+            width: 1000,
+            height: 500,
+            resized: vec![variant("/_images/a-1-480.png", 480)],
+            converted: vec![(
+                OutputFormat::Webp,
+                vec![
+                    variant("/_images/a-1-480.webp", 480),
+                    variant("/_images/a-1-1000.webp", 1000),
+                ],
+            )],
+        };
+        let settings = ImagesConfig::default();
+
+        let mut attrs = parse_attrs(r#"<img src="/a.png" alt="A">"#);
+        assert_eq!(
+            render_tag(&mut attrs, &image, false, &settings),
+            concat!(
+                r#"<picture><source type="image/webp" srcset="/_images/a-1-480.webp 480w, /_images/a-1-1000.webp 1000w">"#,
+                r#"<img src="/a.png" alt="A" width="1000" height="500" loading="lazy" srcset="/_images/a-1-480.png 480w, /a.png 1000w">"#,
+                "</picture>"
+            )
+        );
+
+        // Authors' own dimensions, loading and srcset are kept
+        let mut attrs =
+            parse_attrs(r#"<img src="/a.png" width="200" loading="eager" srcset="/a.png 1x">"#);
+        assert_eq!(
+            render_tag(&mut attrs, &image, false, &settings),
+            r#"<img src="/a.png" width="200" loading="eager" srcset="/a.png 1x">"#
+        );
+
+        let mut attrs = parse_attrs(r#"<img src="/a.png">"#);
+        assert!(!render_tag(&mut attrs, &image, true, &settings).contains("<picture>"));
+
+        let mut attrs = parse_attrs(r#"<img src="/a.png" sizes='a"b'>"#);
+        assert!(render_tag(&mut attrs, &image, false, &settings)
+            .starts_with(r#"<picture><source type="image/webp" srcset="/_images/a-1-480.webp 480w, /_images/a-1-1000.webp 1000w" sizes="a&quot;b">"#));
+    }
+
+    #[test]
+    fn test_in_picture() {
+        let at_end = |html: &str| in_picture(html, html.len(), &raw_text_ranges(html));
+        assert!(at_end("<p><picture><source srcset=\"a\">"));
+        assert!(!at_end("<picture></picture><p>"));
+        assert!(!at_end("<p>"));
+        assert!(!at_end("<script>let p = \"<picture>\";</script><p>"));
+        assert!(at_end("<picture><script>let p = \"</picture>\";</script>"));
+    }
+
+    #[test]
+    fn test_images_in_scripts() {
+        let dir = fixture("images-scripts", &[]);
+        let public = dir.join("src/public");
+        fs::create_dir_all(&public).unwrap();
+        DynamicImage::new_rgb8(4, 2)
+            .save(public.join("a.png"))
+            .unwrap();
+        let settings = ImagesConfig {
+            enabled: true,
+            formats: Vec::new(),
+            ..ImagesConfig::default()
+        };
+
+        let script = "<script>el.innerHTML = '<img src=\"/a.png\">';</script>";
+        let html = format!("{}<img src=\"/a.png\">", script);
+        let out_dir = dir.join("dist");
+        let result =
+            process_images_with(html, &out_dir.join("index.html"), &dir, &out_dir, &settings);
+        assert!(result.errors.is_empty());
+        assert_eq!(
+            result.output,
+            format!(
+                "{}<img src=\"/a.png\" width=\"4\" height=\"2\" loading=\"lazy\">",
+                script
+            )
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_unused() {
+        let dir = fixture("images-prune", &[("a-1-480.png", ""), ("b-2-480.png", "")]);
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        let keep = HashSet::from(["a-1-480.png".to_string()]);
+        remove_unused(&dir, &keep).unwrap();
+        assert!(dir.join("a-1-480.png").exists());
+        assert!(!dir.join("b-2-480.png").exists());
+        assert!(dir.join("nested").exists());
+        remove_unused(&dir.join("missing"), &keep).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
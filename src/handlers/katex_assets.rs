@@ -1,30 +1,35 @@
+use crate::config;
+use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::utils::copy_into;
 use once_cell::sync::Lazy;
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 
-// Track if KaTeX was used in the current page (thread-local)
-thread_local! {
-    static KATEX_USED: AtomicBool = const { AtomicBool::new(false) };
-}
-
 // Track if we've printed the KaTeX message (global, one-time)
 static MESSAGE_PRINTED: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(false));
 
-pub fn mark_katex_used() {
-    KATEX_USED.with(|used| used.store(true, Ordering::Relaxed));
-}
+// Track if any page in the current build used KaTeX (global)
+static USED_IN_BUILD: AtomicBool = AtomicBool::new(false);
+
+const KATEX_CSS: &str = "katex.min.css";
 
-pub fn was_katex_used() -> bool {
-    KATEX_USED.with(|used| used.load(Ordering::Relaxed))
+pub fn mark_used_in_build() {
+    USED_IN_BUILD.store(true, Ordering::Relaxed);
 }
 
-pub fn reset_katex_flag() {
-    KATEX_USED.with(|used| used.store(false, Ordering::Relaxed));
+pub fn reset_build_flag() {
+    USED_IN_BUILD.store(false, Ordering::Relaxed);
 }
 
 pub fn print_katex_message() {
     if !MESSAGE_PRINTED.swap(true, Ordering::Relaxed) {
-        println!("  📐 KaTeX CSS will be injected (using CDN)");
+        if config::get().katex.path.is_some() {
+            println!("Pages with math link the self-hosted KaTeX stylesheet.");
+        } else {
+            println!("Pages with math link the KaTeX stylesheet on jsDelivr.");
+        }
     }
 }
 
@@ -32,7 +37,124 @@ pub fn is_katex_injection_disabled() -> bool {
     env::var("SIMPLE_DISABLE_KATEX_CSS").is_ok()
 }
 
-pub fn get_katex_css_tag() -> &'static str {
+pub fn get_katex_css_tag() -> String {
+    let katex = &config::get().katex;
+    if katex.path.is_some() {
+        return format!(
+            r#"<!-- KaTeX CSS (auto-injected, self-hosted) -->
+<link rel="stylesheet" href="{}{}/{}">"#,
+            config::get().base,
+            katex.output,
+            KATEX_CSS
+        );
+    }
+
     r#"<!-- KaTeX CSS (auto-injected from CDN) -->
 <link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/katex@0.16.11/dist/katex.min.css" integrity="sha384-nB0miv6/jRmo5UMMR1wu3Gz6NLsoTkbqJghGIsx//Rlm+ZU03BU6SQNC66uf4l5+" crossorigin="anonymous">"#
+        .to_string()
+}
+
+/// Copy the stylesheet and fonts from the configured KaTeX `dist` folder into
+/// the output, if any page in the build rendered math.
+pub fn copy_katex_assets(dir: &Path, dist: &Path) -> Result<(), ProcessError> {
+    let katex = &config::get().katex;
+    let Some(path) = &katex.path else {
+        return Ok(());
+    };
+    let used = USED_IN_BUILD.load(Ordering::Relaxed) && !is_katex_injection_disabled();
+    copy_katex_assets_with(&dir.join(path), &dist.join(&katex.output), used)
+}
+
+/// Copy `from`, a KaTeX `dist` folder, to `to` if `used` is set.
+fn copy_katex_assets_with(from: &Path, to: &Path, used: bool) -> Result<(), ProcessError> {
+    if !used {
+        return Ok(());
+    }
+
+    let css = from.join(KATEX_CSS);
+    if !css.exists() {
+        return Err(ProcessError {
+            error_type: ErrorType::Io,
+            item: WithItem::File,
+            path: css,
+            message: Some(format!(
+                "The KaTeX path in the config must point at KaTeX's dist folder, with {} and fonts/ inside.",
+                KATEX_CSS
+            )),
+        });
+    }
+
+    fs::create_dir_all(to).map_proc_err(WithItem::File, ErrorType::Io, &to.to_path_buf(), None)?;
+    let css_to = to.join(KATEX_CSS);
+    fs::copy(&css, &css_to).map_proc_err(WithItem::File, ErrorType::Io, &css_to, None)?;
+    copy_into(&from.join("fonts"), &to.join("fonts"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::pages::page;
+    use crate::utils::fixture;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_copy_katex_assets() {
+        let dir = fixture(
+            "katex-assets",
+            &[
+                ("katex/katex.min.css", ".katex{}"),
+                ("katex/fonts/KaTeX_Main-Regular.woff2", "font"),
+            ],
+        );
+        let from = dir.join("katex");
+        let to = dir.join("dist/katex");
+
+        // Nothing is copied unless a page used math
+        copy_katex_assets_with(&from, &to, false).unwrap();
+        assert!(!to.exists());
+
+        copy_katex_assets_with(&from, &to, true).unwrap();
+        assert!(to.join(KATEX_CSS).exists());
+        assert!(to.join("fonts/KaTeX_Main-Regular.woff2").exists());
+
+        // A folder without the stylesheet isn't a KaTeX dist folder
+        let err = copy_katex_assets_with(&dir.join("fonts"), &to, true).unwrap_err();
+        assert!(err.path.ends_with(KATEX_CSS));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_math_in_components_and_templates() {
+        let src = fixture(
+            "katex-nested",
+            &[
+                (
+                    "components/Formula.component.html",
+                    "<markdown>$a + b$</markdown>",
+                ),
+                (
+                    "templates/Formulas.template.html",
+                    "<markdown>$${f}$</markdown>",
+                ),
+            ],
+        );
+        let render = |body: &str| page(&src, body.to_string(), HashSet::new(), &[]);
+
+        let result = render("<p>No math here</p>");
+        assert!(!result.assets.katex);
+
+        let result = render("<Formula />");
+        assert!(result.errors.is_empty());
+        assert!(result.output.contains("class=\"katex\""));
+        assert!(result.assets.katex);
+
+        let result = render(concat!(
+            "<data name=\"Formulas\">[{\"f\": \"x^2\"}]</data>",
+            "<::Template{Formulas} />"
+        ));
+        assert!(result.errors.is_empty());
+        assert!(result.output.contains("class=\"katex\""));
+        assert!(result.assets.katex);
+        fs::remove_dir_all(src).unwrap();
+    }
 }
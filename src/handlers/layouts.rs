@@ -0,0 +1,166 @@
+use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::assets::Assets;
+use crate::handlers::components::fill_slot;
+use crate::handlers::frontmatter::{has_frontmatter, parse_frontmatter};
+use crate::utils::ProcessResult;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Wrap `content` in the layout component `name`. A layout can extend another
+/// layout through a `layout` key in its own frontmatter, in which case the
+/// chain is followed until a layout without one is reached.
+///
+/// Frontmatter values of each layout are added to `vars` as defaults, so values
+/// set by the page (or a more specific layout) take precedence.
+pub fn apply_layout(
+    src: &Path,
+    name: &str,
+    content: String,
+    vars: &mut HashMap<String, String>,
+) -> ProcessResult {
+    let mut errors: Vec<ProcessError> = Vec::new();
+    let mut hist: HashSet<PathBuf> = HashSet::new();
+    let mut output = content;
+    let mut next = Some(name.to_string());
+
+    while let Some(layout) = next.take() {
+        let path = src
+            .join("components")
+            .join(layout.replace(":", "/"))
+            .with_extension("component.html");
+
+        if !hist.insert(path.clone()) {
+            errors.push(ProcessError {
+                error_type: ErrorType::Circular,
+                item: WithItem::Component,
+                path,
+                message: Some(format!("{:?}", hist)),
+            });
+            break;
+        }
+
+        let st = match fs::read_to_string(&path).map_proc_err(
+            WithItem::Component,
+            ErrorType::Io,
+            &path,
+            Some(format!("Failed to read layout '{}'", layout)),
+        ) {
+            Ok(content) => content,
+            Err(e) => {
+                errors.push(e);
+                break;
+            }
+        };
+
+        let body = if has_frontmatter(&st) {
+            match parse_frontmatter(&st, &path) {
+                Ok(fm_result) => {
+                    errors.extend(fm_result.warnings);
+                    for (k, v) in fm_result.map {
+                        if k == "layout" {
+                            next = Some(v);
+                        } else {
+                            vars.entry(k).or_insert(v);
+                        }
+                    }
+                    fm_result.remaining
+                }
+                Err(e) => {
+                    errors.push(e);
+                    break;
+                }
+            }
+        } else {
+            st
+        };
+
+        if !body.contains("<slot>") || !body.contains("</slot>") {
+            errors.push(ProcessError {
+                error_type: ErrorType::Syntax,
+                item: WithItem::Component,
+                path,
+                message: Some(String::from(
+                    "The layout does not contain a proper <slot></slot> tag.",
+                )),
+            });
+            break;
+        }
+
+        let filled = fill_slot(&body, &output, &path);
+        errors.extend(filled.errors);
+        output = filled.output;
+    }
+
+    ProcessResult {
+        output,
+        errors,
+        assets: Assets::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::components::{process_component, ComponentTypes};
+    use crate::utils::fixture;
+
+    #[test]
+    fn test_layout_chain() {
+        let src = fixture(
+            "layouts-chain",
+            &[
+                (
+                    "components/Post.component.html",
+                    "---\nlayout: Base\nsection: Blog\n---\n<article><slot></slot></article>",
+                ),
+                (
+                    "components/Base.component.html",
+                    "---\ntitle: Untitled\nsection: Site\n---\n<main><slot></slot></main>",
+                ),
+            ],
+        );
+        let mut vars = HashMap::from([("title".to_string(), "Hello".to_string())]);
+        let result = apply_layout(&src, "Post", "Hi".to_string(), &mut vars);
+        assert!(result.errors.is_empty());
+        assert_eq!(result.output, "<main><article>Hi</article></main>");
+        // The page's values win, then the closest layout's
+        assert_eq!(vars["title"], "Hello");
+        assert_eq!(vars["section"], "Blog");
+        assert!(!vars.contains_key("layout"));
+
+        // Used as a plain component, a layout's frontmatter isn't output
+        let result = process_component(
+            &src,
+            "<Post>Hi</Post>".to_string(),
+            ComponentTypes::Wrapping,
+            HashSet::new(),
+            &[],
+        );
+        assert_eq!(result.output, "<article>Hi</article>");
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    #[test]
+    fn test_layout_cycle() {
+        let src = fixture(
+            "layouts-cycle",
+            &[
+                (
+                    "components/A.component.html",
+                    "---\nlayout: B\n---\n<a><slot></slot></a>",
+                ),
+                (
+                    "components/B.component.html",
+                    "---\nlayout: A\n---\n<b><slot></slot></b>",
+                ),
+            ],
+        );
+        let result = apply_layout(&src, "A", "Hi".to_string(), &mut HashMap::new());
+        assert_eq!(result.errors.len(), 1);
+        assert!(matches!(result.errors[0].error_type, ErrorType::Circular));
+        fs::remove_dir_all(src).unwrap();
+    }
+}
@@ -1,13 +1,21 @@
-use comrak::markdown_to_html_with_plugins;
+use comrak::adapters::{HeadingAdapter, HeadingMeta};
+use comrak::format_html_with_plugins;
+use comrak::nodes::{LineColumn, NodeValue, Sourcepos};
 use comrak::plugins::syntect::SyntectAdapterBuilder;
-use comrak::{options::Plugins, Options};
+use comrak::{options::Plugins, parse_document, Anchorizer, Arena, Options};
 use fancy_regex::Regex;
 use katex::{Opts, OutputType};
 use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fmt;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
+use crate::config::{self, KatexOutput, MarkdownConfig};
 use crate::error::{ErrorType, ProcessError, WithItem};
-use crate::handlers::katex_assets;
+use crate::handlers::assets::Assets;
+use crate::handlers::callouts;
+use crate::handlers::code_blocks::CodeBlocks;
 use crate::utils::{self, ProcessResult};
 use crate::IS_DEV;
 
@@ -16,12 +24,38 @@ const MD_CLOSE_PLACEHOLDER: &str = "\x00simple_md_close\x00";
 
 /// Restore placeholders back to real markdown tags.
 fn unshield(s: &str) -> String {
-    s.replace(MD_OPEN_PLACEHOLDER, "<markdown>")
+    s.replace(MD_OPEN_PLACEHOLDER, "<markdown")
         .replace(MD_CLOSE_PLACEHOLDER, "</markdown>")
 }
 
 static MARKDOWN_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r#"(?<!<!--)<markdown>([\s\S]+?)<\/markdown>(?!-->)"#)
+    Regex::new(r#"(?<!<!--)<markdown(\s[^>]*)?>([\s\S]+?)<\/markdown>(?!-->)"#)
+        .expect("Regex failed to parse. This shouldn't happen.")
+});
+
+static LINK_DEFINITION_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^ {0,3}\[[^\]^][^\]]*\]:[ \t]*<?([^\s>]+)"#)
+        .expect("Regex failed to parse. This shouldn't happen.")
+});
+
+const LT_PLACEHOLDER: &str = "\x00simple_lt\x00";
+
+/// Stands in for a `<toc />` line while comrak renders the block, so the table
+/// of contents doesn't depend on raw HTML being let through.
+const TOC_PLACEHOLDER: &str = "%%simple-toc-here%%";
+
+/// Stands in for `${--toc}` in a frame. Replaced once the page's markdown is
+/// rendered with the table of contents of the block starting with
+/// [`TOC_SOURCE`], so its links match the ids the headings actually got.
+pub const TOC_MARKER: &str = "%%simple-toc%%";
+
+/// Put right after an opening `<markdown>` tag to make that block's headings
+/// the ones listed at [`TOC_MARKER`].
+pub const TOC_SOURCE: &str = "%%simple-toc-source%%";
+
+/// Opening, closing or self-closing component tag.
+static COMPONENT_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"</?[A-Z][A-Za-z_]*(?::[A-Z][A-Za-z_]*)*(?:\s+[A-Za-z]+=(['"]).*?\1)*\s*/?>"#)
         .expect("Regex failed to parse. This shouldn't happen.")
 });
 
@@ -33,23 +67,233 @@ static MATH_SPAN_REGEX: Lazy<Regex> = Lazy::new(|| {
 static SYNTAX_HIGHLIGHTER: Lazy<comrak::plugins::syntect::SyntectAdapter> =
     Lazy::new(|| SyntectAdapterBuilder::new().css().build());
 
-fn create_markdown_options() -> Options<'static> {
+fn create_markdown_options(settings: &MarkdownConfig) -> Options<'static> {
     let mut options = Options::default();
-    options.extension.math_code = true;
-    options.extension.math_dollars = true;
-    options.extension.superscript = true;
-    options.extension.footnotes = true;
-    options.extension.strikethrough = true;
-    options.extension.autolink = true;
-    options.extension.table = true;
-    options.extension.tasklist = true;
-    options.extension.footnotes = true;
-    options.extension.highlight = true;
-    options.render.r#unsafe = true;
+    options.extension.math_code = settings.math;
+    options.extension.math_dollars = settings.math;
+    options.extension.superscript = settings.superscript;
+    options.extension.footnotes = settings.footnotes;
+    options.extension.strikethrough = settings.strikethrough;
+    options.extension.autolink = settings.autolink;
+    options.extension.table = settings.tables;
+    options.extension.tasklist = settings.tasklists;
+    options.extension.highlight = settings.highlight;
+    options.extension.description_lists = settings.description_lists;
+    options.parse.smart = settings.smart;
+    options.render.hardbreaks = settings.hardbreaks;
+    options.render.r#unsafe = settings.unsafe_html;
+    // Hands the whole info string to the code block renderer
+    options.render.github_pre_lang = true;
+    options.render.full_info_string = true;
     options
 }
 
-fn render_katex(html: &str) -> (String, Vec<ProcessError>) {
+/// A heading as it appears in the table of contents.
+struct TocEntry {
+    level: u8,
+    id: String,
+    text: String,
+}
+
+/// Gives every heading a slugged `id`, deduplicated by `anchorizer`, and
+/// records them for the table of contents.
+struct HeadingIds<'a> {
+    anchorizer: &'a Mutex<Anchorizer>,
+    headings: Mutex<Vec<TocEntry>>,
+    anchor_links: bool,
+}
+
+impl<'a> HeadingIds<'a> {
+    fn new(anchorizer: &'a Mutex<Anchorizer>, anchor_links: bool) -> Self {
+        HeadingIds {
+            anchorizer,
+            headings: Mutex::new(Vec::new()),
+            anchor_links,
+        }
+    }
+
+    fn anchorize(&self, text: &str) -> String {
+        match self.anchorizer.lock() {
+            Ok(mut anchorizer) => anchorizer.anchorize(text),
+            Err(poisoned) => poisoned.into_inner().anchorize(text),
+        }
+    }
+
+    fn take_headings(&self) -> Vec<TocEntry> {
+        match self.headings.lock() {
+            Ok(mut headings) => std::mem::take(&mut *headings),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        }
+    }
+}
+
+impl HeadingAdapter for HeadingIds<'_> {
+    fn enter(
+        &self,
+        output: &mut dyn fmt::Write,
+        heading: &HeadingMeta,
+        _sourcepos: Option<Sourcepos>,
+    ) -> fmt::Result {
+        let id = self.anchorize(&heading.content);
+        write!(output, "<h{} id=\"{}\">", heading.level, id)?;
+        if self.anchor_links {
+            write!(
+                output,
+                "<a class=\"heading-anchor\" href=\"#{}\" aria-hidden=\"true\">#</a>",
+                id
+            )?;
+        }
+
+        if let Ok(mut headings) = self.headings.lock() {
+            headings.push(TocEntry {
+                level: heading.level,
+                id,
+                text: heading.content.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn exit(&self, output: &mut dyn fmt::Write, heading: &HeadingMeta) -> fmt::Result {
+        write!(output, "</h{}>", heading.level)
+    }
+}
+
+/// Render headings as a nested list of links. Nesting is relative to the
+/// shallowest heading, so a document starting at `##` isn't indented.
+fn render_toc(headings: &[TocEntry]) -> String {
+    let Some(min_level) = headings.iter().map(|h| h.level).min() else {
+        return String::new();
+    };
+
+    let mut out = String::from(r#"<nav class="toc">"#);
+    let mut depth = 0;
+    for heading in headings {
+        let level = (heading.level - min_level) as usize + 1;
+        if level > depth {
+            while depth < level {
+                out.push_str("<ul><li>");
+                depth += 1;
+            }
+        } else {
+            while depth > level {
+                out.push_str("</li></ul>");
+                depth -= 1;
+            }
+            out.push_str("</li><li>");
+        }
+        out.push_str(&format!(
+            r##"<a href="#{}">{}</a>"##,
+            heading.id,
+            utils::escape_xml(&heading.text)
+        ));
+    }
+    while depth > 0 {
+        out.push_str("</li></ul>");
+        depth -= 1;
+    }
+    out.push_str("</nav>");
+    out
+}
+
+/// Rewrite the destinations of the links and images in a markdown document.
+/// `resolve` is called once for each distinct destination and returns what to
+/// replace it with, or `None` to leave it alone.
+pub fn rewrite_links<F>(input: &str, mut resolve: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let arena = Arena::new();
+    let options = create_markdown_options(&config::get().markdown);
+    let root = parse_document(&arena, input, &options);
+
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let offset = |pos: LineColumn| {
+        line_starts
+            .get(pos.line.checked_sub(1)?)
+            .map(|start| start + pos.column.saturating_sub(1))
+    };
+
+    let mut resolved: HashMap<String, Option<String>> = HashMap::new();
+    let mut edits: Vec<(usize, usize, String)> = Vec::new();
+    let mut code_lines = Vec::new();
+
+    for node in root.descendants() {
+        let data = node.data();
+        let link = match &data.value {
+            NodeValue::Link(link) | NodeValue::Image(link) => link,
+            NodeValue::CodeBlock(_) => {
+                code_lines.push(data.sourcepos.start.line..=data.sourcepos.end.line);
+                continue;
+            }
+            _ => continue,
+        };
+        let replacement = resolved
+            .entry(link.url.clone())
+            .or_insert_with(|| resolve(&link.url))
+            .clone();
+        let (Some(replacement), Some(start), Some(end)) = (
+            replacement,
+            offset(data.sourcepos.start),
+            offset(data.sourcepos.end),
+        ) else {
+            continue;
+        };
+
+        // Inline links end in `](destination "title")`. Reference links have
+        // no destination here and are handled with their definitions below.
+        let Some(span) = input.get(start..=end) else {
+            continue;
+        };
+        let Some(open) = span.rfind("](") else {
+            continue;
+        };
+        let rest = &span[open + 2..];
+        let dest = start + open + 2 + (rest.len() - rest.trim_start().len());
+        let dest = dest + usize::from(input[dest..].starts_with('<'));
+        if input[dest..].starts_with(link.url.as_str()) {
+            edits.push((dest, dest + link.url.len(), replacement));
+        }
+    }
+
+    // Link reference definitions, e.g. `[intro]: ./intro.md`
+    for (index, line_start) in line_starts.iter().enumerate() {
+        if code_lines.iter().any(|lines| lines.contains(&(index + 1))) {
+            continue;
+        }
+        let line = input[*line_start..].lines().next().unwrap_or("");
+        let Ok(Some(caps)) = LINK_DEFINITION_REGEX.captures(line) else {
+            continue;
+        };
+        let Some(dest) = caps.get(1) else { continue };
+        if let Some(Some(replacement)) = resolved.get(dest.as_str()) {
+            edits.push((
+                line_start + dest.start(),
+                line_start + dest.end(),
+                replacement.clone(),
+            ));
+        }
+    }
+
+    edits.sort_by_key(|(start, _, _)| *start);
+    let mut output = String::with_capacity(input.len());
+    let mut last_end = 0;
+    for (start, end, replacement) in edits {
+        if start < last_end {
+            continue;
+        }
+        output.push_str(&input[last_end..start]);
+        output.push_str(&replacement);
+        last_end = end;
+    }
+    output.push_str(&input[last_end..]);
+    output
+}
+
+/// Render the math spans in `html`. Returns whether any math was rendered.
+fn render_katex(html: &str) -> (String, Vec<ProcessError>, bool) {
     let mut errors = Vec::new();
     let mut result = String::with_capacity(html.len() + (html.len() >> 1));
     let mut last_end = 0;
@@ -76,11 +320,27 @@ fn render_katex(html: &str) -> (String, Vec<ProcessError>) {
                     None => continue,
                 };
 
-                let opts = match Opts::builder()
-                    .output_type(OutputType::Html)
+                let katex = &config::get().katex;
+                let mut builder = Opts::builder();
+                builder
+                    .output_type(match katex.output_type {
+                        KatexOutput::Html => OutputType::Html,
+                        KatexOutput::Mathml => OutputType::Mathml,
+                        KatexOutput::Both => OutputType::HtmlAndMathml,
+                    })
                     .display_mode(style == "display")
-                    .build()
-                {
+                    .throw_on_error(katex.throw_on_error)
+                    .trust(katex.trust)
+                    .fleqn(katex.fleqn)
+                    .leqno(katex.leqno);
+                if let Some(color) = &katex.error_color {
+                    builder.error_color(color.clone());
+                }
+                for (name, expansion) in &katex.macro_definitions {
+                    builder = builder.add_macro(name.clone(), expansion.clone());
+                }
+
+                let opts = match builder.build() {
                     Ok(opts) => opts,
                     Err(e) => {
                         errors.push(ProcessError {
@@ -133,45 +393,171 @@ fn render_katex(html: &str) -> (String, Vec<ProcessError>) {
     }
 
     result.push_str(&html[last_end..]);
+    (result, errors, has_math)
+}
+
+fn component_placeholder(index: usize) -> String {
+    format!("%%simple-component-{}%%", index)
+}
+
+/// Swap component tags for placeholders so comrak leaves them alone. A tag on a
+/// line of its own is a block: it's kept out of paragraphs and the markdown
+/// between an opening and closing tag is still rendered. Anything else is
+/// inline. Tags in code blocks and code spans are left as they are. A `<toc />`
+/// line becomes a placeholder for the block's table of contents.
+fn protect_components(input: &str) -> (String, Vec<String>) {
+    let shielded = utils::shield_fenced_code_with_replacements(input, &[("<", LT_PLACEHOLDER)]);
+    let mut out = String::with_capacity(shielded.len());
+    let mut tags = Vec::new();
+
+    for line in shielded.split_inclusive('\n') {
+        if matches!(line.trim(), "<toc />" | "<toc/>") {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            out.push('\n');
+            out.push_str(indent);
+            out.push_str(TOC_PLACEHOLDER);
+            out.push_str("\n\n");
+            continue;
+        }
+        if !line.contains('<') {
+            out.push_str(line);
+            continue;
+        }
+
+        let mut last_end = 0;
+        for found in COMPONENT_TAG_REGEX.find_iter(line).flatten() {
+            // Inside a code span
+            if line[..found.start()].matches('`').count() % 2 == 1 {
+                continue;
+            }
+
+            let placeholder = component_placeholder(tags.len());
+            tags.push(found.as_str().to_string());
+
+            if line.trim() == found.as_str() {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                out.push('\n');
+                out.push_str(indent);
+                out.push_str(&placeholder);
+                out.push_str("\n\n");
+                last_end = line.len();
+                break;
+            }
+
+            out.push_str(&line[last_end..found.start()]);
+            out.push_str(&placeholder);
+            last_end = found.end();
+        }
+        out.push_str(&line[last_end..]);
+    }
+
+    (out.replace(LT_PLACEHOLDER, "<"), tags)
+}
+
+/// Put the component tags back in place of their placeholders.
+fn restore_components(html: String, tags: &[String]) -> String {
+    let mut html = html;
+    for (index, tag) in tags.iter().enumerate() {
+        let placeholder = component_placeholder(index);
+        html = html
+            .replace(&format!("<p>{}</p>", placeholder), tag)
+            .replace(&placeholder, tag);
+    }
+    html
+}
+
+/// Render one markdown block, along with the headings it got ids for.
+/// `anchorizer` is shared by the blocks of a page so their heading ids don't
+/// collide.
+fn render_markdown_with(
+    input: &str,
+    settings: &MarkdownConfig,
+    anchorizer: &Mutex<Anchorizer>,
+) -> (ProcessResult, Vec<TocEntry>) {
+    let (input, tags) = protect_components(input);
+    let input = input.as_str();
+    let heading_ids = HeadingIds::new(anchorizer, settings.heading_anchors);
+    let mut plugins = Plugins::default();
+    let code_blocks = CodeBlocks::new(&SYNTAX_HIGHLIGHTER);
+    plugins.render.codefence_syntax_highlighter = Some(&code_blocks);
+    if settings.heading_ids || settings.heading_anchors || input.contains(TOC_PLACEHOLDER) {
+        plugins.render.heading_adapter = Some(&heading_ids);
+    }
+    let options = create_markdown_options(settings);
 
-    if has_math {
-        katex_assets::mark_katex_used();
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+    let callouts = if config::get().callouts.enabled {
+        callouts::mark_callouts(root, &arena)
+    } else {
+        Vec::new()
+    };
+
+    let mut errors = Vec::new();
+    let mut rendered = String::with_capacity(input.len() * 2);
+    if let Err(e) = format_html_with_plugins(root, &options, &mut rendered, &plugins) {
+        errors.push(ProcessError {
+            error_type: ErrorType::Other,
+            item: WithItem::None,
+            path: PathBuf::new(),
+            message: Some(format!("Failed to render markdown: {}", e)),
+        });
     }
 
-    (result, errors)
+    let headings = heading_ids.take_headings();
+    if rendered.contains(TOC_PLACEHOLDER) {
+        let toc = render_toc(&headings);
+        rendered = rendered
+            .replace(&format!("<p>{}</p>", TOC_PLACEHOLDER), &toc)
+            .replace(TOC_PLACEHOLDER, &toc);
+    }
+    let rendered = callouts::restore_callouts(rendered, &callouts);
+    let (output, katex_errors, has_math) = render_katex(&rendered);
+    errors.extend(katex_errors);
+    let has_code = output.contains("<pre");
+    let result = ProcessResult {
+        output: restore_components(output, &tags),
+        errors,
+        assets: Assets {
+            katex: has_math,
+            callouts: !callouts.is_empty(),
+            highlight: has_code,
+        },
+    };
+    (result, headings)
 }
 
 pub fn render_markdown(input: String) -> ProcessResult {
     let mut errors = Vec::new();
+    let mut assets = Assets::default();
 
     // Early return if no markdown
     if !input.contains("</markdown>") {
         return ProcessResult {
             output: input,
             errors,
+            assets: Assets::default(),
         };
     }
 
-    let mut plugins = Plugins::default();
-    plugins.render.codefence_syntax_highlighter = Some(&*SYNTAX_HIGHLIGHTER);
-    let options = create_markdown_options();
-
     let is_dev = *IS_DEV.get().unwrap_or(&false);
+    let anchorizer = Mutex::new(Anchorizer::new());
+    let mut toc = None;
 
     // Shield markdown tags inside fenced code blocks so the regex skips them
     let shielded = utils::shield_fenced_code_with_replacements(
         &input,
         &[
-            ("<markdown>", MD_OPEN_PLACEHOLDER),
+            ("<markdown", MD_OPEN_PLACEHOLDER),
             ("</markdown>", MD_CLOSE_PLACEHOLDER),
         ],
     );
     let mut result = String::with_capacity(input.len() + (input.len() >> 2));
     let mut last_end = 0;
 
-    for captures in MARKDOWN_REGEX.find_iter(&shielded) {
-        let mat = match captures {
-            Ok(m) => m,
+    for captures in MARKDOWN_REGEX.captures_iter(&shielded) {
+        let cap = match captures {
+            Ok(cap) => cap,
             Err(e) => {
                 errors.push(ProcessError {
                     error_type: ErrorType::Other,
@@ -182,19 +568,49 @@ pub fn render_markdown(input: String) -> ProcessResult {
                 continue;
             }
         };
+        let (Some(mat), Some(content)) = (cap.get(0), cap.get(2)) else {
+            continue;
+        };
         let start = mat.start();
         let end = mat.end();
 
         result.push_str(&shielded[last_end..start]);
 
-        // Extract content between <markdown> and </markdown>, restore placeholders
-        let markdown_content = unshield(&shielded[start + 10..end - 11]);
-        let unindented = utils::unindent(&markdown_content);
-        let rendered = markdown_to_html_with_plugins(&unindented, &options, &plugins);
+        // Attributes on the opening tag override the project's options
+        let mut settings = config::get().markdown.clone();
+        if let Some(attributes) = cap.get(1) {
+            let tag = format!("<markdown{}>", attributes.as_str());
+            match utils::get_targets_kv("markdown", &tag) {
+                Ok(targets) => {
+                    for (key, value) in targets {
+                        if let Err(message) = settings.set(key, value) {
+                            errors.push(ProcessError {
+                                error_type: ErrorType::Syntax,
+                                item: WithItem::None,
+                                path: PathBuf::new(),
+                                message: Some(message),
+                            });
+                        }
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        }
 
-        // Render KaTeX math expressions
-        let (rendered, katex_errors) = render_katex(&rendered);
-        errors.extend(katex_errors);
+        // Extract content between the tags, restore placeholders
+        let markdown_content = unshield(content.as_str());
+        let (markdown_content, is_toc_source) = match markdown_content.strip_prefix(TOC_SOURCE) {
+            Some(rest) => (rest, true),
+            None => (markdown_content.as_str(), false),
+        };
+        let unindented = utils::unindent(markdown_content);
+        let (rendered, headings) = render_markdown_with(&unindented, &settings, &anchorizer);
+        if is_toc_source {
+            toc = Some(render_toc(&headings));
+        }
+        errors.extend(rendered.errors);
+        assets.merge(rendered.assets);
+        let rendered = rendered.output;
 
         if is_dev {
             result.push_str(r#"<div style='display: contents;' data-markdown-source=""#);
@@ -220,8 +636,136 @@ pub fn render_markdown(input: String) -> ProcessResult {
     }
 
     result.push_str(&shielded[last_end..]);
+    if let Some(toc) = toc {
+        result = result.replace(TOC_MARKER, &toc);
+    }
     ProcessResult {
         output: result,
         errors,
+        assets,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(input: &str) -> ProcessResult {
+        render_markdown_with(
+            input,
+            &config::get().markdown,
+            &Mutex::new(Anchorizer::new()),
+        )
+        .0
+    }
+
+    #[test]
+    fn test_heading_ids_and_toc() {
+        let input = "<toc />\n\n# Intro\n\n## Setup\n\n## Setup\n\n# Usage\n";
+        let html = render(input).output;
+        assert!(html.contains(r#"<h1 id="intro">Intro</h1>"#));
+        assert!(html.contains(r#"<h2 id="setup">Setup</h2>"#));
+        assert!(html.contains(r#"<h2 id="setup-1">Setup</h2>"#));
+
+        let toc = concat!(
+            r##"<nav class="toc"><ul><li><a href="#intro">Intro</a>"##,
+            r##"<ul><li><a href="#setup">Setup</a></li><li><a href="#setup-1">Setup</a></li></ul>"##,
+            r##"</li><li><a href="#usage">Usage</a></li></ul></nav>"##
+        );
+        assert!(html.contains(toc));
+        assert!(!html.contains("<toc"));
+        assert!(!render("```\n<toc />\n```\n").output.contains("<nav"));
+
+        assert!(render("# Plain\n").output.contains("<h1>Plain</h1>"));
+    }
+
+    #[test]
+    fn test_heading_ids_across_blocks() {
+        let input = concat!(
+            "<markdown heading_ids=\"true\">\n# Overview\n</markdown>\n",
+            "<markdown heading_ids=\"true\">\n# Overview\n</markdown>",
+        );
+        let html = render_markdown(input.to_string()).output;
+        assert!(html.contains(r#"<h1 id="overview">Overview</h1>"#));
+        assert!(html.contains(r#"<h1 id="overview-1">Overview</h1>"#));
+    }
+
+    #[test]
+    fn test_frame_toc() {
+        let input = format!(
+            "<markdown heading_ids=\"true\">\n# Intro\n</markdown>\n{}\n<markdown heading_ids=\"true\">{}\n# Intro\n\n## Setup\n</markdown>",
+            TOC_MARKER, TOC_SOURCE
+        );
+        let html = render_markdown(input).output;
+        assert!(html.contains(r#"<h1 id="intro-1">Intro</h1>"#));
+        assert!(html.contains(concat!(
+            r##"<nav class="toc"><ul><li><a href="#intro-1">Intro</a>"##,
+            r##"<ul><li><a href="#setup">Setup</a></li></ul></li></ul></nav>"##
+        )));
+        assert!(!html.contains("%%simple-toc"));
+    }
+
+    #[test]
+    fn test_components_in_markdown() {
+        let input = "Intro with <Badge text=\"new\" /> inline.\n<Callout type=\"warn\">\nThis is **important**.\n</Callout>\n\n`<Code />`\n\n```html\n<Fenced />\n```\n";
+        let html = render(input).output;
+        assert!(html.contains("<p>Intro with <Badge text=\"new\" /> inline.</p>"));
+        assert!(html.contains(
+            "<Callout type=\"warn\">\n<p>This is <strong>important</strong>.</p>\n</Callout>"
+        ));
+        assert!(html.contains("<code>&lt;Code /&gt;</code>"));
+        assert!(!html.contains("<Fenced />"));
+    }
+
+    #[test]
+    fn test_callouts() {
+        let input = "> [!NOTE]\n> Read **this**.\n\n> [!warning] Careful\n> Hot.\n\n> [!NOPE]\n> Plain quote.\n";
+        let html = render(input).output;
+        assert!(html.contains(concat!(
+            "<aside class=\"callout callout-note\">\n",
+            "<p class=\"callout-title\">Note</p>\n",
+            "<p>Read <strong>this</strong>.</p>\n</aside>"
+        )));
+        assert!(html.contains("<p class=\"callout-title\">Careful</p>\n<p>Hot.</p>"));
+        assert!(html.contains("<blockquote>\n<p>[!NOPE]"));
+    }
+
+    #[test]
+    fn test_block_attributes() {
+        let input = "<markdown smart=\"true\" hardbreaks=\"true\">\n\"a\"\nb\n</markdown>";
+        let result = render_markdown(input.to_string());
+        assert!(result.errors.is_empty());
+        assert!(result.output.contains("<p>“a”<br />\nb</p>"));
+
+        let result = render_markdown("<markdown smart=\"yes\">x</markdown>".to_string());
+        assert_eq!(result.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_rewrite_links() {
+        let input = concat!(
+            "[a](./a.md) and ![img](<./b c.png> \"t\") and [ext](https://x.org)\n\n",
+            "[ref][r] [a again](./a.md#top)\n\n",
+            "```\n[r]: ./a.md\n```\n\n",
+            "[r]: ./a.md\n",
+        );
+        let mut calls = Vec::new();
+        let output = rewrite_links(input, |url| {
+            calls.push(url.to_string());
+            (!url.starts_with("https:")).then(|| format!("/{}", url.trim_start_matches("./")))
+        });
+        assert_eq!(
+            output,
+            concat!(
+                "[a](/a.md) and ![img](</b c.png> \"t\") and [ext](https://x.org)\n\n",
+                "[ref][r] [a again](/a.md#top)\n\n",
+                "```\n[r]: ./a.md\n```\n\n",
+                "[r]: /a.md\n",
+            )
+        );
+        assert_eq!(
+            calls,
+            ["./a.md", "./b c.png", "https://x.org", "./a.md#top"]
+        );
     }
 }
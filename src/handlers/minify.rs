@@ -0,0 +1,453 @@
+use crate::config;
+use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::refs::{css_refs, replace_ranges, resolve, Link};
+use crate::utils::{normalize_path, walk_dir};
+use color_print::ceprintln;
+use fancy_regex::Regex;
+use lightningcss::stylesheet::{MinifyOptions, ParserOptions, PrinterOptions, StyleSheet};
+use once_cell::sync::Lazy;
+use oxc_allocator::Allocator;
+use oxc_codegen::{Codegen, CodegenOptions};
+use oxc_minifier::{CompressOptions, MangleOptions, Minifier, MinifierOptions};
+use oxc_parser::Parser;
+use oxc_span::SourceType;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// An `@import` statement: the URL, and any media, `supports()` or `layer`
+/// conditions after it.
+static IMPORT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"@import\s+(?:url\(\s*(?:"([^"]*)"|'([^']*)'|([^)\s'"]*))\s*\)|"([^"]*)"|'([^']*)')\s*([^;]*);"#,
+    )
+    .unwrap()
+});
+
+/// A string or a comment in a stylesheet. Strings are matched so a `/*` inside
+/// one doesn't start a comment.
+static COMMENT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#""(?:[^"\\\n]|\\.)*"|'(?:[^'\\\n]|\\.)*'|(/\*[\s\S]*?(?:\*/|$))"#).unwrap()
+});
+
+/// Options for minifying pages. Inline styles and scripts are minified too
+/// unless `inline` is turned off, and never in dev mode.
+pub fn html_cfg(dev: bool) -> minify_html::Cfg {
+    let inline = !dev && config::get().minify.inline;
+    minify_html::Cfg {
+        minify_css: inline,
+        minify_js: inline,
+        ..minify_html::Cfg::new()
+    }
+}
+
+/// Bundle and minify the stylesheets and scripts copied from `public` to
+/// `dist`. Files that fail to parse are left as they are, with a warning.
+pub fn minify_assets(public: &Path, dist: &Path) -> Result<(), Vec<ProcessError>> {
+    let settings = &config::get().minify;
+    if !(settings.css || settings.js || settings.bundle) || !public.exists() {
+        return Ok(());
+    }
+
+    let files = walk_dir(&public.to_path_buf()).map_err(|e| vec![e])?;
+    let mut errors = Vec::new();
+    for file in &files {
+        let Ok(path) = file.strip_prefix(public) else {
+            continue;
+        };
+        let ext = path
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let is_css = ext.eq_ignore_ascii_case("css");
+        let is_js = ext.eq_ignore_ascii_case("js") || ext.eq_ignore_ascii_case("mjs");
+        if !(is_css && (settings.css || settings.bundle) || is_js && settings.js) {
+            continue;
+        }
+
+        let source = match fs::read_to_string(file).map_proc_err(
+            WithItem::File,
+            ErrorType::Io,
+            file,
+            None,
+        ) {
+            Ok(source) => source,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+
+        // Already minified files are only bundled
+        let minify = !is_minified(path);
+        let output = if is_css {
+            let css = if settings.bundle {
+                bundle_css(path, &source, &mut |target| read_stylesheet(public, target))
+            } else {
+                source
+            };
+            if settings.css && minify {
+                minify_css(&css).unwrap_or_else(|e| {
+                    warn(file, &e);
+                    css
+                })
+            } else {
+                css
+            }
+        } else if minify {
+            let module = ext.eq_ignore_ascii_case("mjs");
+            minify_js(&source, module).unwrap_or_else(|e| {
+                warn(file, &e);
+                source
+            })
+        } else {
+            continue;
+        };
+
+        let out = dist.join(path);
+        if let Err(e) =
+            fs::write(&out, output).map_proc_err(WithItem::File, ErrorType::Io, &out, None)
+        {
+            errors.push(e);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn warn(file: &Path, message: &str) {
+    ceprintln!(
+        "<y>Warning</>: Couldn't minify {}, copying it as is: {}",
+        file.display(),
+        message
+    );
+}
+
+/// `app.min.js` and the like.
+fn is_minified(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .is_some_and(|stem| stem.ends_with(".min"))
+}
+
+fn read_stylesheet(public: &Path, path: &Path) -> Option<String> {
+    let is_css = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("css"));
+    if is_css {
+        fs::read_to_string(public.join(path)).ok()
+    } else {
+        None
+    }
+}
+
+pub fn minify_css(css: &str) -> Result<String, String> {
+    let mut sheet = StyleSheet::parse(css, ParserOptions::default()).map_err(|e| e.to_string())?;
+    sheet
+        .minify(MinifyOptions::default())
+        .map_err(|e| e.to_string())?;
+    let printer = PrinterOptions {
+        minify: true,
+        ..PrinterOptions::default()
+    };
+    sheet
+        .to_css(printer)
+        .map(|out| out.code)
+        .map_err(|e| e.to_string())
+}
+
+/// Minify a script. Names at the top level are kept, since other scripts
+/// and inline handlers may use them. `.js` files are treated as modules if
+/// they import or export anything.
+pub fn minify_js(js: &str, module: bool) -> Result<String, String> {
+    let allocator = Allocator::default();
+    let source_type = if module {
+        SourceType::mjs()
+    } else {
+        SourceType::unambiguous()
+    };
+    let parsed = Parser::new(&allocator, js, source_type).parse();
+    if let Some(error) = parsed.errors.first() {
+        return Err(error.to_string());
+    }
+
+    let mut program = parsed.program;
+    let minified = Minifier::new(MinifierOptions {
+        mangle: Some(MangleOptions::default()),
+        compress: Some(CompressOptions::safest()),
+    })
+    .minify(&allocator, &mut program);
+    let options = CodegenOptions {
+        minify: true,
+        ..CodegenOptions::default()
+    };
+    // The mangled names are only applied when printing
+    Ok(Codegen::new()
+        .with_options(options)
+        .with_scoping(minified.scoping)
+        .with_private_member_mappings(minified.class_private_mappings)
+        .build(&program)
+        .code)
+}
+
+/// Replace the local `@import`s in the stylesheet at `path` (relative to
+/// `src/public`) with the contents of the imported files, loaded with
+/// `load`. Relative `url()`s in imported files are rebased to `path`.
+///
+/// Imports with conditions, of other sites, or of files that can't be
+/// loaded are kept and moved to the top, where `@import` has to be.
+fn bundle_css<F>(path: &Path, css: &str, load: &mut F) -> String
+where
+    F: FnMut(&Path) -> Option<String>,
+{
+    let (imports, body) = bundle_imports(path, css, load, &mut vec![path.to_path_buf()]);
+    let mut output = String::new();
+    for import in imports {
+        output.push_str(&import);
+        output.push('\n');
+    }
+    output.push_str(&body);
+    output
+}
+
+/// The `@import`s kept in `css` and the rest of it, with local imports
+/// inlined. `visiting` holds the files being bundled, to break cycles.
+fn bundle_imports<F>(
+    path: &Path,
+    css: &str,
+    load: &mut F,
+    visiting: &mut Vec<PathBuf>,
+) -> (Vec<String>, String)
+where
+    F: FnMut(&Path) -> Option<String>,
+{
+    let base = &config::get().base;
+    let mut imports = Vec::new();
+    let mut replacements = Vec::new();
+
+    // Commented out imports stay commented out. Blanking the comments keeps
+    // the offsets the same as in `css`.
+    let scanned = blank_comments(css);
+    for caps in IMPORT_REGEX.captures_iter(&scanned).filter_map(|c| c.ok()) {
+        let statement = caps.get(0).unwrap();
+        let url = (1..=5)
+            .find_map(|i| caps.get(i))
+            .map(|m| m.as_str())
+            .unwrap_or_default();
+        let unconditional = caps.get(6).is_none_or(|m| m.as_str().trim().is_empty());
+
+        let target = match resolve(url, path, base) {
+            Link::Internal(target, _) if unconditional => Some(target),
+            _ => None,
+        };
+        let inlined = match target {
+            // Already being bundled further up, so importing it again would
+            // only repeat its rules
+            Some(target) if visiting.contains(&target) => Some(String::new()),
+            Some(target) => load(&target).map(|text| {
+                visiting.push(target.clone());
+                let (nested, body) = bundle_imports(&target, &text, load, visiting);
+                visiting.pop();
+                imports.extend(
+                    nested
+                        .iter()
+                        .map(|import| rebase_urls(import, &target, path)),
+                );
+                rebase_urls(&body, &target, path)
+            }),
+            None => None,
+        };
+
+        match inlined {
+            Some(body) => replacements.push((statement.range(), body)),
+            None => {
+                imports.push(statement.as_str().to_string());
+                replacements.push((statement.range(), String::new()));
+            }
+        }
+    }
+
+    (imports, replace_ranges(css, replacements))
+}
+
+/// `css` with each comment replaced by as many spaces as it has bytes.
+fn blank_comments(css: &str) -> String {
+    let comments: Vec<_> = COMMENT_REGEX
+        .captures_iter(css)
+        .filter_map(|c| {
+            c.ok()?
+                .get(1)
+                .map(|m| (m.range(), " ".repeat(m.as_str().len())))
+        })
+        .collect();
+    replace_ranges(css, comments)
+}
+
+/// Point the relative `url()`s and `@import`s in `css`, a stylesheet at
+/// `from`, at the same files from a stylesheet at `to`.
+fn rebase_urls(css: &str, from: &Path, to: &Path) -> String {
+    let from_dir = from.parent().unwrap_or(Path::new(""));
+    let to_dir = to.parent().unwrap_or(Path::new(""));
+    if from_dir == to_dir {
+        return css.to_string();
+    }
+
+    let replacements = css_refs(css)
+        .into_iter()
+        .filter_map(|range| {
+            let url = &css[range.clone()];
+            if url.starts_with('/') || url.starts_with('#') {
+                return None;
+            }
+            if !matches!(resolve(url, from, "/"), Link::Internal(..) | Link::Outside) {
+                return None;
+            }
+            let path_end = url.find(['?', '#']).unwrap_or(url.len());
+            let target = normalize_path(&from_dir.join(&url[..path_end]));
+            let rebased = relative_path(to_dir, &target);
+            Some((
+                range,
+                format!(
+                    "{}{}",
+                    rebased.to_string_lossy().replace('\\', "/"),
+                    &url[path_end..]
+                ),
+            ))
+        })
+        .collect();
+    replace_ranges(css, replacements)
+}
+
+/// Path of `target` relative to the folder `dir`. Both are normalized and
+/// relative to the same root.
+fn relative_path(dir: &Path, target: &Path) -> PathBuf {
+    let dir: Vec<Component> = dir.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = dir.iter().zip(&target).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    for component in &target[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("css"), Path::new("img/a.png")),
+            PathBuf::from("../img/a.png")
+        );
+        assert_eq!(
+            relative_path(Path::new(""), Path::new("css/fonts/a.woff2")),
+            PathBuf::from("css/fonts/a.woff2")
+        );
+        assert_eq!(
+            relative_path(Path::new("css/vendor"), Path::new("css/a.png")),
+            PathBuf::from("../a.png")
+        );
+    }
+
+    #[test]
+    fn test_rebase_urls() {
+        let css = r#"@font-face { src: url("fonts/a b.woff2?v=2") } .a { background: url(../img/x.png) } .b { mask: url(data:x) } .c { background: url(/img/y.png) }"#;
+        assert_eq!(
+            rebase_urls(
+                css,
+                Path::new("css/vendor/fonts.css"),
+                Path::new("styles.css")
+            ),
+            r#"@font-face { src: url("css/vendor/fonts/a b.woff2?v=2") } .a { background: url(css/img/x.png) } .b { mask: url(data:x) } .c { background: url(/img/y.png) }"#
+        );
+    }
+
+    #[test]
+    fn test_bundle_css() {
+        let files = HashMap::from([
+            (
+                "css/base.css",
+                "@import url(reset.css);\n.a { background: url(img/a.png) }",
+            ),
+            (
+                "css/reset.css",
+                "@import \"../styles.css\";\n* { margin: 0 }",
+            ),
+        ]);
+        let css = concat!(
+            "@import \"css/base.css\";\n",
+            "@import \"https://fonts.example.org/a.css\";\n",
+            "@import \"print.css\" print;\n",
+            "@import \"missing.css\";\n",
+            "body { color: red }",
+        );
+        let bundled = bundle_css(Path::new("styles.css"), css, &mut |path| {
+            files
+                .get(path.to_str().unwrap())
+                .map(|text| text.to_string())
+        });
+        assert_eq!(
+            bundled,
+            concat!(
+                "@import \"https://fonts.example.org/a.css\";\n",
+                "@import \"print.css\" print;\n",
+                "@import \"missing.css\";\n",
+                "\n",
+                "* { margin: 0 }\n",
+                ".a { background: url(css/img/a.png) }\n",
+                "\n",
+                "\n",
+                "\n",
+                "body { color: red }",
+            )
+        );
+    }
+
+    #[test]
+    fn test_bundle_css_comments() {
+        let css = concat!(
+            "/* @import \"a.css\"; */\n",
+            ".a { content: \"/*\" }\n",
+            "@import \"a.css\";\n",
+            "/* unclosed @import \"a.css\";",
+        );
+        let bundled = bundle_css(Path::new("styles.css"), css, &mut |_| {
+            Some(".imported {}".to_string())
+        });
+        assert_eq!(
+            bundled,
+            concat!(
+                "/* @import \"a.css\"; */\n",
+                ".a { content: \"/*\" }\n",
+                ".imported {}\n",
+                "/* unclosed @import \"a.css\";",
+            )
+        );
+    }
+
+    #[test]
+    fn test_minify() {
+        assert_eq!(
+            minify_css(".a {\n  color: #ff0000;\n  margin: 0px;\n}\n").unwrap(),
+            ".a{color:red;margin:0}"
+        );
+        assert!(minify_css(".a { color: red").is_ok());
+
+        let js = minify_js("function greet(name) {\n  const message = 'Hello, ' + name;\n  console.log(message);\n}\n", false).unwrap();
+        assert!(js.starts_with("function greet("), "{}", js);
+        assert!(!js.contains("message"), "{}", js);
+        assert!(minify_js("import { a } from './a.js';\nexport const b = a;", false).is_ok());
+        assert!(minify_js("const = ;", false).is_err());
+    }
+}
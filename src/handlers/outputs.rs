@@ -0,0 +1,119 @@
+use crate::dates::Date;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A page written during the current build.
+#[derive(Clone, Debug)]
+pub struct Output {
+    /// Path of the written file.
+    pub path: PathBuf,
+    /// URL relative to the site root (without the base path), e.g. `/about/`.
+    pub url: String,
+    pub lastmod: Option<Date>,
+    /// Whether the page should be listed in the sitemap.
+    pub sitemap: bool,
+}
+
+// Pages are written from several threads, and entries from deep inside
+// template expansion, so they're collected here rather than passed back up.
+static OUTPUTS: Lazy<Mutex<Vec<Output>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+pub fn reset() {
+    if let Ok(mut outputs) = OUTPUTS.lock() {
+        outputs.clear();
+    }
+}
+
+/// Record a page written to `path` inside the output directory `out_dir`.
+/// `vars` are the page's frontmatter values, used for `lastmod` (falling back
+/// to the modification time of `source`) and the `sitemap: false` opt-out.
+pub fn record(out_dir: &Path, path: &Path, source: &Path, vars: &[(&str, &str)]) {
+    let output = output_for(out_dir, path, source, vars);
+    if let Ok(mut outputs) = OUTPUTS.lock() {
+        outputs.push(output);
+    }
+}
+
+fn output_for(out_dir: &Path, path: &Path, source: &Path, vars: &[(&str, &str)]) -> Output {
+    let get = |key: &str| vars.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+
+    let lastmod = ["lastmod", "updated", "date"]
+        .iter()
+        .find_map(|key| get(key).and_then(Date::parse))
+        .or_else(|| {
+            fs::metadata(source)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(Date::from_system_time)
+        });
+
+    Output {
+        path: path.to_path_buf(),
+        url: url_for(out_dir, path),
+        lastmod,
+        sitemap: get("sitemap") != Some("false"),
+    }
+}
+
+/// All pages written so far, sorted by URL. Entries listed by several pages
+/// are only included once.
+pub fn all() -> Vec<Output> {
+    let outputs = match OUTPUTS.lock() {
+        Ok(outputs) => outputs.clone(),
+        Err(_) => return Vec::new(),
+    };
+
+    let mut unique: HashMap<PathBuf, Output> = HashMap::new();
+    for output in outputs {
+        unique.insert(output.path.clone(), output);
+    }
+
+    let mut outputs: Vec<Output> = unique.into_values().collect();
+    outputs.sort_by(|a, b| a.url.cmp(&b.url));
+    outputs
+}
+
+/// `about/index.html` → `/about/`, `about.html` → `/about.html`.
+pub fn url_for(out_dir: &Path, path: &Path) -> String {
+    let relative = path
+        .strip_prefix(out_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/");
+    let relative = relative.strip_suffix("index.html").unwrap_or(&relative);
+    format!("/{}", relative.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_for() {
+        let out_dir = Path::new("dist");
+        let source = Path::new("src/pages/missing.html");
+
+        let output = output_for(
+            out_dir,
+            Path::new("dist/blog/post/index.html"),
+            source,
+            &[("date", "Jan 15 2025"), ("updated", "2025-02-01")],
+        );
+        assert_eq!(output.url, "/blog/post/");
+        assert_eq!(output.lastmod.unwrap().to_string(), "2025-02-01");
+        assert!(output.sitemap);
+
+        let output = output_for(
+            out_dir,
+            Path::new("dist/drafts.html"),
+            source,
+            &[("sitemap", "false")],
+        );
+        assert_eq!(output.url, "/drafts.html");
+        assert!(output.lastmod.is_none());
+        assert!(!output.sitemap);
+    }
+}
@@ -1,15 +1,26 @@
+use crate::config;
 use crate::dev::{SCRIPT, WS_PORT};
 use crate::error::{errors_to_html, ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::assets::{inject_assets, Assets};
 use crate::handlers::components::{process_component, ComponentTypes};
-use crate::handlers::katex_assets;
+use crate::handlers::frontmatter::{has_frontmatter, parse_page_frontmatter};
+use crate::handlers::images::process_images;
+use crate::handlers::layouts::apply_layout;
 use crate::handlers::markdown::render_markdown;
-use crate::handlers::templates::process_template;
-use crate::utils::ProcessResult;
+use crate::handlers::minify::html_cfg;
+use crate::handlers::outputs;
+use crate::handlers::templates::{data_block, extract_inline_data, process_template};
+use crate::utils::{apply_base, fill_base, kv_replace_markup, pretty_path, ProcessResult};
 use crate::IS_DEV;
 use minify_html::minify;
 use rayon::prelude::*;
+use serde_json::Value;
 use std::sync::Arc;
-use std::{collections::HashSet, fs, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
 fn process_step<F>(
     func: F,
@@ -17,52 +28,80 @@ fn process_step<F>(
     string: &mut String,
     hist: &HashSet<PathBuf>,
     vec_errs: &mut Vec<ProcessError>,
+    assets: &mut Assets,
 ) where
     F: Fn(&PathBuf, String, &HashSet<PathBuf>) -> ProcessResult,
 {
     let result = func(src, std::mem::take(string), hist);
     *string = result.output;
     vec_errs.extend(result.errors);
+    assets.merge(result.assets);
 }
 
-pub fn page(src: &PathBuf, mut string: String, hist: HashSet<PathBuf>) -> ProcessResult {
+/// Expand markdown, components and templates in `string`. `vars` are the
+/// page-level variables (e.g. from frontmatter), which are passed down to every
+/// component and template used by the page.
+pub fn page(
+    src: &PathBuf,
+    mut string: String,
+    hist: HashSet<PathBuf>,
+    vars: &[(&str, &str)],
+) -> ProcessResult {
     let mut errors: Vec<ProcessError> = Vec::new();
+    let mut assets = Assets::default();
+
+    string = fill_base(string);
 
     if string.contains("</markdown>") {
         let md_result = render_markdown(string);
         string = md_result.output;
         errors.extend(md_result.errors);
+        assets.merge(md_result.assets);
     }
 
     process_step(
         |srcpath, str, hist| {
-            process_component(srcpath, str, ComponentTypes::Wrapping, hist.clone())
+            process_component(srcpath, str, ComponentTypes::Wrapping, hist.clone(), vars)
         },
         src,
         &mut string,
         &hist,
         &mut errors,
+        &mut assets,
     );
     process_step(
         |srcpath, str, hist| {
-            process_component(srcpath, str, ComponentTypes::SelfClosing, hist.clone())
+            process_component(
+                srcpath,
+                str,
+                ComponentTypes::SelfClosing,
+                hist.clone(),
+                vars,
+            )
         },
         src,
         &mut string,
         &hist,
         &mut errors,
+        &mut assets,
     );
+    let (data_result, inline_data) = extract_inline_data(string);
+    string = data_result.output;
+    errors.extend(data_result.errors);
+
     process_step(
-        |srcpath, str, hist| process_template(srcpath, str, hist.clone()),
+        |srcpath, str, hist| process_template(srcpath, str, hist.clone(), &inline_data, vars),
         src,
         &mut string,
         &hist,
         &mut errors,
+        &mut assets,
     );
 
     ProcessResult {
         output: string,
         errors,
+        assets,
     }
 }
 
@@ -89,7 +128,7 @@ pub fn process_pages(
     };
 
     let working_dir = if dev { "dev" } else { "dist" };
-    let minify_cfg = Arc::new(minify_html::Cfg::new());
+    let minify_cfg = Arc::new(html_cfg(dev));
 
     let mut file_tasks = Vec::new();
     let mut dir_tasks = Vec::new();
@@ -164,9 +203,6 @@ fn process_single_file(
 ) -> Vec<ProcessError> {
     let mut errors: Vec<ProcessError> = Vec::new();
 
-    // Reset KaTeX usage flag for this page
-    katex_assets::reset_katex_flag();
-
     let file_content =
         match fs::read_to_string(&path).map_proc_err(WithItem::File, ErrorType::Io, &path, None) {
             Ok(content) => content,
@@ -188,7 +224,40 @@ fn process_single_file(
         return errors;
     }
 
-    let result = page(&src, file_content, HashSet::new());
+    // Strip the optional frontmatter; its fields become variables for the page
+    let (body, mut page_vars, page_data) = if has_frontmatter(&file_content) {
+        match parse_page_frontmatter(&file_content, &path) {
+            Ok(fm_result) => {
+                errors.extend(fm_result.warnings);
+                (fm_result.remaining, fm_result.map, fm_result.data)
+            }
+            Err(e) => {
+                errors.push(e);
+                write_error_page_if_dev(dev, &errors, &dir, &src, &path, &working_dir);
+                return errors;
+            }
+        }
+    } else {
+        (file_content, HashMap::new(), Vec::new())
+    };
+
+    // The sitemap only goes by the page's own values, not its layouts' defaults
+    let own_vars = page_vars.clone();
+    let body = prepare_body(
+        &src,
+        &path,
+        body,
+        &mut page_vars,
+        &page_data,
+        config::get().markdown.layout.as_deref(),
+    );
+    errors.extend(body.errors);
+
+    let vars: Vec<(&str, &str)> = page_vars
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    let result = page(&src, body.output, HashSet::new(), &vars);
     errors.extend(result.errors);
 
     let out_path = match resolve_out_path(&path, &dir, &src, &working_dir) {
@@ -211,6 +280,11 @@ fn process_single_file(
         }
     }
 
+    let output = inject_assets(apply_base(result.output), result.assets);
+    let images = process_images(output, &out_path, &dir, &dir.join(&working_dir));
+    errors.extend(images.errors);
+    let mut output = images.output;
+
     // If there are errors: dev → error page, build → skip
     if !errors.is_empty() {
         if dev {
@@ -221,22 +295,6 @@ fn process_single_file(
         return errors;
     }
 
-    let mut output = result.output;
-
-    // Inject KaTeX CSS if math was rendered (unless disabled)
-    if katex_assets::was_katex_used() && !katex_assets::is_katex_injection_disabled() {
-        katex_assets::print_katex_message();
-
-        if output.contains("<head>") {
-            output = output.replace(
-                "<head>",
-                &format!("<head>\n{}", katex_assets::get_katex_css_tag()),
-            );
-        } else {
-            output = format!("{}\n{}", katex_assets::get_katex_css_tag(), output);
-        }
-    }
-
     if dev {
         let ws_port = *WS_PORT.get().unwrap();
         if !output.contains("// * SCRIPT INCLUDED IN DEV MODE") {
@@ -258,11 +316,75 @@ fn process_single_file(
             path: out_path,
             message: Some(format!("Failed to write file: {}", e)),
         });
+    } else {
+        let own_vars: Vec<(&str, &str)> = own_vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        outputs::record(&dir.join(&working_dir), &out_path, &path, &own_vars);
     }
 
     errors
 }
 
+/// Get a page's body ready for [`page`]: wrap markdown pages in a `<markdown>`
+/// block, add the frontmatter's template data, apply the layout and fill in
+/// the page's variables. `page_vars` gains the layouts' defaults. Markdown
+/// pages that don't name a layout get `markdown_layout`.
+fn prepare_body(
+    src: &Path,
+    path: &Path,
+    body: String,
+    page_vars: &mut HashMap<String, String>,
+    page_data: &[(String, Value)],
+    markdown_layout: Option<&str>,
+) -> ProcessResult {
+    let mut errors = Vec::new();
+
+    let is_markdown = is_markdown_page(path);
+    let body = if is_markdown {
+        format!("<markdown>\n{}</markdown>", body)
+    } else {
+        body
+    };
+    // Lists in the frontmatter are inline data for the page's templates
+    let body = page_data
+        .iter()
+        .map(|(name, value)| data_block(name, value))
+        .collect::<String>()
+        + &body;
+    let layout = match page_vars.get("layout") {
+        Some(layout) => Some(layout.clone()),
+        None if is_markdown => markdown_layout.map(str::to_string),
+        None => None,
+    };
+
+    let body = match layout {
+        Some(layout) => {
+            let layout_result = apply_layout(src, &layout, body, page_vars);
+            errors.extend(layout_result.errors);
+            layout_result.output
+        }
+        None => body,
+    };
+
+    let output = if page_vars.is_empty() {
+        body
+    } else {
+        let vars = page_vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        kv_replace_markup(vars, body)
+    };
+
+    ProcessResult {
+        output,
+        errors,
+        assets: Assets::default(),
+    }
+}
+
 fn resolve_out_path(
     path: &PathBuf,
     dir: &PathBuf,
@@ -285,7 +407,17 @@ fn resolve_out_path(
             message: Some(format!("Failed to strip pages prefix: {}", e)),
         })?;
 
-    Ok(dir.join(working_dir).join(relative_to_pages))
+    let out_path = dir.join(working_dir).join(relative_to_pages);
+
+    if is_markdown_page(path) {
+        Ok(pretty_path(out_path.with_extension("html")))
+    } else {
+        Ok(pretty_path(out_path))
+    }
+}
+
+fn is_markdown_page(path: &Path) -> bool {
+    path.extension().and_then(|s| s.to_str()) == Some("md")
 }
 
 fn make_dev_script() -> Option<String> {
@@ -315,3 +447,79 @@ fn write_error_page_if_dev(
         let _ = fs::write(&out_path, error_html.as_bytes());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixture;
+
+    fn render(src: &Path, path: &str, body: &str, vars: &[(&str, &str)]) -> String {
+        let mut page_vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let body = prepare_body(
+            src,
+            Path::new(path),
+            body.to_string(),
+            &mut page_vars,
+            &[],
+            Some("Base"),
+        );
+        assert!(body.errors.is_empty());
+        let vars: Vec<(&str, &str)> = page_vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let result = page(&src.to_path_buf(), body.output, HashSet::new(), &vars);
+        assert!(result.errors.is_empty());
+        result.output
+    }
+
+    #[test]
+    fn test_markdown_page() {
+        let src = fixture(
+            "pages-markdown",
+            &[
+                (
+                    "components/Base.component.html",
+                    "<main><slot></slot></main>",
+                ),
+                ("components/Docs.component.html", "<div><slot></slot></div>"),
+            ],
+        );
+        let body = "# ${title}\n\nSome *notes*.\n";
+
+        // Markdown pages get the default layout unless they name one
+        assert_eq!(
+            render(&src, "pages/notes.md", body, &[("title", "Notes")]),
+            concat!(
+                "<main><div style='display: contents;'>",
+                "<h1>Notes</h1>\n<p>Some <em>notes</em>.</p>\n</div></main>"
+            )
+        );
+        assert_eq!(
+            render(
+                &src,
+                "pages/notes.md",
+                body,
+                &[("title", "Notes"), ("layout", "Docs")]
+            ),
+            concat!(
+                "<div><div style='display: contents;'>",
+                "<h1>Notes</h1>\n<p>Some <em>notes</em>.</p>\n</div></div>"
+            )
+        );
+        // HTML pages don't
+        assert_eq!(
+            render(
+                &src,
+                "pages/index.html",
+                "<h1>${title}</h1>",
+                &[("title", "Home")]
+            ),
+            "<h1>Home</h1>"
+        );
+        fs::remove_dir_all(src).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+use crate::utils::{normalize_path, percent_decode};
+use fancy_regex::Regex;
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+static TAG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[A-Za-z][^>]*>").unwrap());
+
+static ATTR_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"\s([A-Za-z-]+)=(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap());
+
+/// Scripts and styles, whose contents aren't markup.
+static RAW_TEXT_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?is)(<(script|style)\b[^>]*>)(.*?)</\2\s*>").unwrap());
+
+/// `url(...)` and `@import "..."`.
+static CSS_URL_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^)\s'"]*))\s*\)|@import\s+(?:"([^"]*)"|'([^']*)')"#,
+    )
+    .unwrap()
+});
+
+/// Module specifiers in `import`/`export ... from` statements and `import()`.
+static JS_IMPORT_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?:\bfrom|\bimport)\s*\(?\s*(?:"([^"\n]*)"|'([^'\n]*)')"#).unwrap());
+
+static SCHEME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Za-z][A-Za-z0-9+.-]*:").unwrap());
+
+/// Where a reference leads.
+#[derive(Debug, PartialEq)]
+pub enum Link {
+    /// `mailto:`, `data:` and other links that can't be followed.
+    Skipped,
+    External,
+    /// A file relative to the output directory, and the fragment if any.
+    Internal(PathBuf, Option<String>),
+    /// A root-relative link outside the base path, or a relative one that
+    /// climbs out of the site.
+    Outside,
+}
+
+/// Byte ranges of the contents of the scripts and styles in `html`.
+pub fn raw_text_ranges(html: &str) -> Vec<Range<usize>> {
+    RAW_TEXT_REGEX
+        .captures_iter(html)
+        .filter_map(|c| c.ok()?.get(3).map(|m| m.range()))
+        .collect()
+}
+
+/// Call `f` with the tag, attribute name and value range of every attribute
+/// in `html`, skipping the contents of scripts and styles.
+fn for_each_attr<F>(html: &str, mut f: F)
+where
+    F: FnMut(&str, &str, Range<usize>),
+{
+    let raw_text = raw_text_ranges(html);
+
+    for tag in TAG_REGEX.find_iter(html).filter_map(|m| m.ok()) {
+        if raw_text.iter().any(|r| r.contains(&tag.start())) {
+            continue;
+        }
+        for caps in ATTR_REGEX
+            .captures_iter(tag.as_str())
+            .filter_map(|c| c.ok())
+        {
+            let Some(value) = caps.get(2).or_else(|| caps.get(3)).or_else(|| caps.get(4)) else {
+                continue;
+            };
+            let range = tag.start() + value.start()..tag.start() + value.end();
+            f(tag.as_str(), &caps[1].to_ascii_lowercase(), range);
+        }
+    }
+}
+
+/// Byte ranges of the URLs in `href`, `src` and `srcset` attributes and CSS
+/// `url()`s in `html`, in order. Values are as written, entities included.
+pub fn html_refs(html: &str) -> Vec<Range<usize>> {
+    let mut refs = Vec::new();
+
+    for caps in RAW_TEXT_REGEX.captures_iter(html).filter_map(|c| c.ok()) {
+        if let (true, Some(body)) = (caps[2].eq_ignore_ascii_case("style"), caps.get(3)) {
+            refs.extend(offset(css_refs(body.as_str()), body.start()));
+        }
+    }
+
+    for_each_attr(html, |_, name, range| match name {
+        "href" | "src" => refs.push(trim_range(html, range)),
+        "srcset" => {
+            let mut start = range.start;
+            for candidate in html[range.clone()].split(',') {
+                let url = candidate.split_whitespace().next().unwrap_or_default();
+                if let Some(pos) = candidate.find(url).filter(|_| !url.is_empty()) {
+                    refs.push(start + pos..start + pos + url.len());
+                }
+                start += candidate.len() + 1;
+            }
+        }
+        "style" => refs.extend(offset(css_refs(&html[range.clone()]), range.start)),
+        _ => {}
+    });
+
+    refs.sort_by_key(|r| r.start);
+    refs
+}
+
+/// Fragment targets in `html`: element `id`s and `<a name>`s.
+pub fn html_ids(html: &str) -> HashSet<String> {
+    let mut ids = HashSet::new();
+    for_each_attr(html, |tag, name, range| {
+        let is_anchor = tag[1..].to_ascii_lowercase().starts_with("a ");
+        if name == "id" || (name == "name" && is_anchor) {
+            ids.insert(decode_entities(&html[range]));
+        }
+    });
+    ids
+}
+
+/// Byte ranges of the URLs in `url()`s and `@import`s in `css`.
+pub fn css_refs(css: &str) -> Vec<Range<usize>> {
+    captured_ranges(&CSS_URL_REGEX, css)
+        .into_iter()
+        .map(|range| trim_range(css, range))
+        .filter(|range| !range.is_empty())
+        .collect()
+}
+
+/// Byte ranges of the module specifiers imported in `js`.
+pub fn js_refs(js: &str) -> Vec<Range<usize>> {
+    captured_ranges(&JS_IMPORT_REGEX, js)
+}
+
+/// Range of the first group that matched, for each match of `regex`.
+fn captured_ranges(regex: &Regex, text: &str) -> Vec<Range<usize>> {
+    regex
+        .captures_iter(text)
+        .filter_map(|c| c.ok())
+        .filter_map(|caps| caps.iter().skip(1).flatten().next().map(|m| m.range()))
+        .collect()
+}
+
+fn offset(ranges: Vec<Range<usize>>, by: usize) -> impl Iterator<Item = Range<usize>> {
+    ranges.into_iter().map(move |r| r.start + by..r.end + by)
+}
+
+fn trim_range(text: &str, range: Range<usize>) -> Range<usize> {
+    let value = &text[range.clone()];
+    let start = range.start + (value.len() - value.trim_start().len());
+    start..start + value.trim().len()
+}
+
+pub fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Replace each range in `text`. Ranges must not overlap.
+pub fn replace_ranges(text: &str, mut replacements: Vec<(Range<usize>, String)>) -> String {
+    replacements.sort_by_key(|(range, _)| range.start);
+    let mut output = String::with_capacity(text.len());
+    let mut last_end = 0;
+    for (range, replacement) in replacements {
+        output.push_str(&text[last_end..range.start]);
+        output.push_str(&replacement);
+        last_end = range.end;
+    }
+    output.push_str(&text[last_end..]);
+    output
+}
+
+/// Resolve `url`, found in the file at `page` (relative to the output
+/// directory), for a site deployed under `base`.
+pub fn resolve(url: &str, page: &Path, base: &str) -> Link {
+    if url.is_empty() {
+        return Link::Skipped;
+    }
+    if url.starts_with("//") {
+        return Link::External;
+    }
+    if SCHEME_REGEX.is_match(url).unwrap_or(false) {
+        let lower = url.to_ascii_lowercase();
+        return if lower.starts_with("http:") || lower.starts_with("https:") {
+            Link::External
+        } else {
+            Link::Skipped
+        };
+    }
+
+    let (rest, fragment) = match url.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(percent_decode(fragment))),
+        None => (url, None),
+    };
+    let path = percent_decode(rest.split('?').next().unwrap_or_default());
+
+    if path.is_empty() {
+        return Link::Internal(page.to_path_buf(), fragment);
+    }
+
+    let (joined, is_dir) = if path.starts_with('/') {
+        let relative = match path.strip_prefix(base) {
+            Some(relative) => relative,
+            None if format!("{}/", path) == base => "",
+            None => return Link::Outside,
+        };
+        (
+            PathBuf::from(relative),
+            relative.is_empty() || relative.ends_with('/'),
+        )
+    } else {
+        (
+            page.parent().unwrap_or(Path::new("")).join(&path),
+            path.ends_with('/'),
+        )
+    };
+
+    let normalized = normalize_path(&joined);
+    if matches!(normalized.components().next(), Some(Component::ParentDir)) {
+        return Link::Outside;
+    }
+    if is_dir {
+        Link::Internal(normalized.join("index.html"), fragment)
+    } else {
+        Link::Internal(normalized, fragment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings<'a>(text: &'a str, ranges: &[Range<usize>]) -> Vec<&'a str> {
+        ranges.iter().map(|r| &text[r.clone()]).collect()
+    }
+
+    #[test]
+    fn test_html_refs() {
+        let html = concat!(
+            r#"<a href="/about/">About</a><h2 id=intro>Intro</h2><a name="old"></a>"#,
+            r#"<img src=a.png srcset="a-480.png 480w, a.png 960w" alt="x&amp;y">"#,
+            r#"<div style="background: url('bg.png')"></div>"#,
+            r#"<style>.x { background: url(/img/x.png) }</style>"#,
+            r#"<script>const a = '<a href="/not-a-link">';</script>"#,
+            r#"<pre><code>&lt;a href="/code"&gt;</code></pre>"#,
+        );
+        assert_eq!(
+            strings(html, &html_refs(html)),
+            [
+                "/about/",
+                "a.png",
+                "a-480.png",
+                "a.png",
+                "bg.png",
+                "/img/x.png"
+            ]
+        );
+        assert_eq!(
+            html_ids(html),
+            HashSet::from(["intro".to_string(), "old".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_css_and_js_refs() {
+        let css =
+            r#"@import "base.css"; .a { background: url( "a b.png" ) } .b { mask: url(data:x) }"#;
+        assert_eq!(
+            strings(css, &css_refs(css)),
+            ["base.css", "a b.png", "data:x"]
+        );
+
+        let js = r#"import a from "./a.js"; import './b.js'; export { c } from "./c.js"; import("./d.js");"#;
+        assert_eq!(
+            strings(js, &js_refs(js)),
+            ["./a.js", "./b.js", "./c.js", "./d.js"]
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let page = Path::new("blog/post.html");
+        let internal = |path: &str, fragment: Option<&str>| {
+            Link::Internal(PathBuf::from(path), fragment.map(str::to_string))
+        };
+
+        assert_eq!(resolve("https://example.org", page, "/"), Link::External);
+        assert_eq!(resolve("//cdn.example.org/a.js", page, "/"), Link::External);
+        assert_eq!(resolve("mailto:a@example.org", page, "/"), Link::Skipped);
+        assert_eq!(
+            resolve("#setup", page, "/"),
+            internal("blog/post.html", Some("setup"))
+        );
+        assert_eq!(
+            resolve("../about/#team", page, "/"),
+            internal("about/index.html", Some("team"))
+        );
+        assert_eq!(
+            resolve("img/a%20b.png?v=1", page, "/"),
+            internal("blog/img/a b.png", None)
+        );
+        assert_eq!(
+            resolve("/docs/guide.html", page, "/docs/"),
+            internal("guide.html", None)
+        );
+        assert_eq!(
+            resolve("/docs", page, "/docs/"),
+            internal("index.html", None)
+        );
+        assert_eq!(resolve("/guide.html", page, "/docs/"), Link::Outside);
+        assert_eq!(resolve("../../a.html", page, "/"), Link::Outside);
+    }
+}
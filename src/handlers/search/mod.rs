@@ -0,0 +1,321 @@
+use crate::config;
+use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::outputs;
+use serde::Serialize;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const SEARCH_BOX: &str = include_str!("./search_box.html");
+
+/// One page in the search index.
+#[derive(Serialize)]
+struct SearchEntry {
+    url: String,
+    title: String,
+    headings: Vec<String>,
+    text: String,
+}
+
+/// Write the search index for every page written during the build.
+pub fn write_search_index(dist: &Path) -> Result<(), Vec<ProcessError>> {
+    let search = &config::get().search;
+    if !search.enabled {
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+    let mut entries = Vec::new();
+
+    for output in outputs::all() {
+        if output.url == "/404.html" {
+            continue;
+        }
+        match fs::read_to_string(&output.path).map_proc_err(
+            WithItem::File,
+            ErrorType::Io,
+            &output.path,
+            None,
+        ) {
+            Ok(html) => {
+                let url = format!("{}{}", config::get().base, &output.url[1..]);
+                entries.push(index_page(url, &html));
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    let path = dist.join(&search.path);
+    match serde_json::to_string(&entries) {
+        Ok(json) => {
+            if let Err(e) =
+                fs::write(&path, json).map_proc_err(WithItem::File, ErrorType::Io, &path, None)
+            {
+                errors.push(e);
+            }
+        }
+        Err(e) => errors.push(ProcessError {
+            error_type: ErrorType::Other,
+            item: WithItem::File,
+            path: PathBuf::from(&search.path),
+            message: Some(format!("Failed to serialize search index: {}", e)),
+        }),
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Markup for the built-in `<SearchBox />` component.
+pub fn search_box(path: &Path) -> Result<String, ProcessError> {
+    let search = &config::get().search;
+    if !search.enabled {
+        return Err(ProcessError {
+            error_type: ErrorType::Other,
+            item: WithItem::Component,
+            path: path.to_path_buf(),
+            message: Some(
+                "<SearchBox /> needs the search index. Set enabled = true under [search] in simple.toml."
+                    .to_string(),
+            ),
+        });
+    }
+
+    let index_url = format!("{}{}", config::get().base, search.path);
+    Ok(SEARCH_BOX.replace("__SIMPLE_SEARCH_INDEX__", &index_url))
+}
+
+/// Pull the title, headings and plain text out of a rendered page.
+fn index_page(url: String, html: &str) -> SearchEntry {
+    let mut title = String::new();
+    let mut headings = Vec::new();
+    let mut text = String::new();
+
+    // ASCII lowercasing keeps byte offsets, so both can be indexed alike
+    let lower = html.to_ascii_lowercase();
+    let content = content_range(&lower);
+
+    let mut heading: Option<String> = None;
+    let mut in_title = false;
+    let mut pos = 0;
+
+    while let Some(offset) = html[pos..].find('<') {
+        let start = pos + offset;
+        let in_content = content.contains(&pos);
+        push_text(
+            &html[pos..start],
+            &mut text,
+            &mut heading,
+            in_title,
+            in_content,
+            &mut title,
+        );
+
+        let Some(end) = html[start..].find('>') else {
+            pos = html.len();
+            break;
+        };
+        let tag = &lower[start + 1..start + end];
+        pos = start + end + 1;
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+
+        match name.as_str() {
+            // Skip everything up to the closing tag
+            "script" | "style" | "template" | "noscript" if !closing => {
+                let close = format!("</{}", name);
+                pos = lower[pos..].find(&close).map_or(html.len(), |i| pos + i);
+            }
+            "title" => in_title = !closing,
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if closing {
+                    if let Some(h) = heading.take() {
+                        let h = collapse_whitespace(&h);
+                        if !h.is_empty() {
+                            headings.push(h);
+                        }
+                    }
+                } else if content.contains(&start) {
+                    heading = Some(String::new());
+                }
+            }
+            _ => {}
+        }
+
+        // Block-level boundaries shouldn't glue words together, but inline
+        // tags like <b> can sit in the middle of one
+        if BLOCK_TAGS.contains(&name.as_str()) {
+            text.push(' ');
+        }
+    }
+    let in_content = content.contains(&pos);
+    push_text(
+        &html[pos..],
+        &mut text,
+        &mut heading,
+        in_title,
+        in_content,
+        &mut title,
+    );
+
+    let title = collapse_whitespace(&title);
+    let title = if title.is_empty() {
+        headings.first().cloned().unwrap_or_default()
+    } else {
+        title
+    };
+
+    SearchEntry {
+        url,
+        title,
+        headings,
+        text: collapse_whitespace(&text),
+    }
+}
+
+/// The part of the page worth indexing: its `<main>`, or from its first to its
+/// last `<article>`, or else the whole page. Navigation and footers repeat on
+/// every page, so they would bloat the index and match every search.
+fn content_range(lower: &str) -> Range<usize> {
+    for tag in ["main", "article"] {
+        let open = format!("<{}", tag);
+        let start = lower.match_indices(&open).map(|(i, _)| i).find(|i| {
+            matches!(
+                lower.as_bytes().get(i + open.len()),
+                Some(b'>' | b'/' | b' ' | b'\t' | b'\n' | b'\r')
+            )
+        });
+        let end = lower.rfind(&format!("</{}", tag));
+        if let (Some(start), Some(end)) = (start, end) {
+            if start < end {
+                return start..end;
+            }
+        }
+    }
+    0..lower.len()
+}
+
+/// Tags that separate words, unlike inline ones such as `<a>` and `<b>`.
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+fn push_text(
+    raw: &str,
+    text: &mut String,
+    heading: &mut Option<String>,
+    in_title: bool,
+    in_content: bool,
+    title: &mut String,
+) {
+    if raw.is_empty() {
+        return;
+    }
+    let decoded = decode_entities(raw);
+    if in_title {
+        title.push_str(&decoded);
+        return;
+    }
+    if !in_content {
+        return;
+    }
+    if let Some(h) = heading {
+        h.push_str(&decoded);
+    }
+    text.push_str(&decoded);
+}
+
+fn decode_entities(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_page() {
+        let html = r#"<html><head><title>Docs &amp; more</title><style>p { color: red; }</style></head>
+<body><h1>Intro</h1><p>Hello <b>wor</b>ld, <a href="/">link</a>.</p><script>let x = "<h2>";</script><h2>Next <em>steps</em></h2><ul><li>One</li><li>Two<br>Three</li></ul><p>Done</p></body></html>"#;
+        let entry = index_page("/docs/".to_string(), html);
+        assert_eq!(entry.title, "Docs & more");
+        assert_eq!(entry.headings, vec!["Intro", "Next steps"]);
+        assert_eq!(
+            entry.text,
+            "Intro Hello world, link. Next steps One Two Three Done"
+        );
+
+        // Only the main content is indexed when the page marks it
+        let html = concat!(
+            "<html><head><title>Guide</title></head><body><nav><a>Home</a><h2>Menu</h2></nav>",
+            "<MAIN class=\"docs\"><h1>Setup</h1><p>Install it.</p></MAIN>",
+            "<footer>Copyright</footer></body></html>"
+        );
+        let entry = index_page("/guide/".to_string(), html);
+        assert_eq!(entry.title, "Guide");
+        assert_eq!(entry.headings, vec!["Setup"]);
+        assert_eq!(entry.text, "Setup Install it.");
+
+        let html = "<header>Site</header><article><h1>A</h1></article><aside>Ad</aside><article><p>B</p></article>";
+        assert_eq!(index_page("/".to_string(), html).text, "A Ad B");
+    }
+}
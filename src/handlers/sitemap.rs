@@ -0,0 +1,124 @@
+use crate::config;
+use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::outputs::{self, Output};
+use crate::utils::escape_xml;
+use std::fs;
+use std::path::Path;
+
+/// Maximum number of URLs in a single sitemap file, per the sitemap protocol.
+const SITEMAP_LIMIT: usize = 50_000;
+
+/// Write `sitemap.xml` for every page written during the build. Large sites get
+/// a sitemap index pointing at `sitemap-1.xml`, `sitemap-2.xml`, etc.
+pub fn write_sitemap(dist: &Path) -> Result<(), ProcessError> {
+    let root = match config::site_root() {
+        Some(root) if config::get().sitemap.enabled => root,
+        _ => return Ok(()),
+    };
+
+    for (name, xml) in sitemaps(&root, &outputs::all(), SITEMAP_LIMIT) {
+        let path = dist.join(name);
+        fs::write(&path, xml).map_proc_err(WithItem::File, ErrorType::Io, &path, None)?;
+    }
+    Ok(())
+}
+
+/// The sitemap files for `outputs` and their names, with at most `limit` URLs
+/// in each.
+fn sitemaps(root: &str, outputs: &[Output], limit: usize) -> Vec<(String, String)> {
+    let pages: Vec<&Output> = outputs
+        .iter()
+        .filter(|o| o.sitemap && o.url != "/404.html")
+        .collect();
+
+    if pages.len() <= limit {
+        return vec![("sitemap.xml".to_string(), urlset(root, &pages))];
+    }
+
+    let mut files = Vec::new();
+    let mut index = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (i, chunk) in pages.chunks(limit).enumerate() {
+        let name = format!("sitemap-{}.xml", i + 1);
+        index.push_str(&format!(
+            "  <sitemap><loc>{}</loc></sitemap>\n",
+            escape_xml(&format!("{}{}", root, name))
+        ));
+        files.push((name, urlset(root, chunk)));
+    }
+    index.push_str("</sitemapindex>\n");
+    files.push(("sitemap.xml".to_string(), index));
+    files
+}
+
+fn urlset(root: &str, pages: &[&Output]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for page in pages {
+        let loc = format!("{}{}", root, page.url.trim_start_matches('/'));
+        xml.push_str(&format!("  <url><loc>{}</loc>", escape_xml(&loc)));
+        if let Some(lastmod) = page.lastmod {
+            xml.push_str(&format!("<lastmod>{}</lastmod>", lastmod));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dates::Date;
+    use std::path::PathBuf;
+
+    fn output(url: &str, sitemap: bool) -> Output {
+        Output {
+            path: PathBuf::from(url),
+            url: url.to_string(),
+            lastmod: Date::parse("2025-01-15"),
+            sitemap,
+        }
+    }
+
+    #[test]
+    fn test_sitemaps() {
+        let root = "https://example.org/docs/";
+        let outputs = vec![
+            output("/", true),
+            output("/404.html", true),
+            output("/about/", true),
+            output("/drafts/", false),
+            output("/blog/a&b/", true),
+        ];
+
+        let files = sitemaps(root, &outputs, 10);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "sitemap.xml");
+        assert_eq!(
+            files[0].1,
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+                "  <url><loc>https://example.org/docs/</loc><lastmod>2025-01-15</lastmod></url>\n",
+                "  <url><loc>https://example.org/docs/about/</loc><lastmod>2025-01-15</lastmod></url>\n",
+                "  <url><loc>https://example.org/docs/blog/a&amp;b/</loc><lastmod>2025-01-15</lastmod></url>\n",
+                "</urlset>\n",
+            )
+        );
+
+        // Past the limit, sitemap.xml becomes an index of numbered files
+        let files = sitemaps(root, &outputs, 2);
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["sitemap-1.xml", "sitemap-2.xml", "sitemap.xml"]);
+        assert_eq!(files[1].1.matches("<url>").count(), 1);
+        assert!(files[2].1.contains("<sitemapindex"));
+        assert!(files[2]
+            .1
+            .contains("<sitemap><loc>https://example.org/docs/sitemap-2.xml</loc></sitemap>"));
+    }
+}
@@ -1,30 +1,141 @@
 use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
-use crate::handlers::entries::process_entry;
+use crate::handlers::assets::Assets;
+use crate::handlers::entries::{collection_entries, process_entry};
 use crate::handlers::frontmatter::load_frontmatter_data;
 use crate::handlers::pages::page;
-use crate::utils::kv_replace;
 use crate::utils::ProcessResult;
+use crate::utils::{get_targets_kv, kv_replace};
 use fancy_regex::Regex;
 use once_cell::sync::Lazy;
 use serde_json::Value;
-use std::{collections::HashSet, fs, path::PathBuf, str};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    str,
+};
 
 static TEMPLATE_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r#"(?<!<!--)<::Template\{([A-Z][A-Za-z_]*(:[A-Z][A-Za-z_]*)*)\}\s*\/>(?!.*?-->)"#)
+    Regex::new(r#"(?<!<!--)<::Template\{([A-Z][A-Za-z_]*(:[A-Z][A-Za-z_]*)*)\}(\s+[A-Za-z][\w-]*=(['\"]).*?\4)*\s*\/>(?!.*?-->)"#)
         .expect("Regex failed to parse. This shouldn't happen.")
 });
 
-pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> ProcessResult {
+static DATA_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?<!<!--)<data\s+name=(['\"])([A-Z][A-Za-z_]*(:[A-Z][A-Za-z_]*)*)\1\s*>([\s\S]*?)<\/data>(?!-->)"#)
+        .expect("Regex failed to parse. This shouldn't happen.")
+});
+
+static TEMPLATE_NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^[A-Z][A-Za-z_]*(:[A-Z][A-Za-z_]*)*$"#)
+        .expect("Regex failed to parse. This shouldn't happen.")
+});
+
+/// Inline data declared with `<data name="Name">...</data>`, keyed by name.
+pub type InlineData = HashMap<String, Value>;
+
+/// Pull `<data name="Name">...</data>` blocks out of the input. The blocks are
+/// removed from the output and their contents (JSON or YAML) are returned for
+/// use by templates in the same page.
+pub fn extract_inline_data(input: String) -> (ProcessResult, InlineData) {
+    let mut errors = Vec::new();
+    let mut data = InlineData::new();
+
+    if !input.contains("</data>") {
+        return (
+            ProcessResult {
+                output: input,
+                errors,
+                assets: Assets::default(),
+            },
+            data,
+        );
+    }
+
+    let mut output = String::with_capacity(input.len());
+    let mut last_end = 0;
+
+    for captures in DATA_REGEX.captures_iter(&input) {
+        let cap = match captures {
+            Ok(cap) => cap,
+            Err(e) => {
+                errors.push(ProcessError {
+                    error_type: ErrorType::Other,
+                    item: WithItem::Data,
+                    path: PathBuf::new(),
+                    message: Some(format!("Regex error while scanning for data blocks: {}", e)),
+                });
+                continue;
+            }
+        };
+        let (mat, name, body) = match (cap.get(0), cap.get(2), cap.get(4)) {
+            (Some(mat), Some(name), Some(body)) => (mat, name.as_str(), body.as_str()),
+            _ => continue,
+        };
+
+        output.push_str(&input[last_end..mat.start()]);
+        last_end = mat.end();
+
+        // YAML is a superset of JSON, so this accepts either.
+        match serde_yaml::from_str::<Value>(body) {
+            Ok(value) => {
+                data.insert(name.to_string(), value);
+            }
+            Err(e) => errors.push(ProcessError {
+                error_type: ErrorType::Syntax,
+                item: WithItem::Data,
+                path: inline_data_path(name),
+                message: Some(format!("Failed to parse inline data: {}", e)),
+            }),
+        }
+    }
+
+    output.push_str(&input[last_end..]);
+    (
+        ProcessResult {
+            output,
+            errors,
+            assets: Assets::default(),
+        },
+        data,
+    )
+}
+
+/// Whether `name` can name a template, like `Posts` or `Blog:Posts`.
+pub fn is_template_name(name: &str) -> bool {
+    TEMPLATE_NAME_REGEX.is_match(name).unwrap_or(false)
+}
+
+/// A `<data>` block holding `value`, for data declared elsewhere (e.g. in a
+/// page's frontmatter) to go through [`extract_inline_data`] like the rest.
+pub fn data_block(name: &str, value: &Value) -> String {
+    format!("<data name=\"{}\">{}</data>\n", name, value)
+}
+
+fn inline_data_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("<data name=\"{}\">", name))
+}
+
+pub fn get_template(
+    src: &PathBuf,
+    name: &str,
+    params: Vec<(&str, &str)>,
+    inline: Option<&Value>,
+    mut hist: HashSet<PathBuf>,
+    vars: &[(&str, &str)],
+) -> ProcessResult {
     let mut errors: Vec<ProcessError> = Vec::new();
     let template_path = src
         .join("templates")
         .join(name.replace(":", "/"))
         .with_extension("template.html");
 
-    let data_path = src
-        .join("data")
-        .join(name.replace(":", "/"))
-        .with_extension("data.json");
+    let data_path = if inline.is_some() {
+        inline_data_path(name)
+    } else {
+        src.join("data")
+            .join(name.replace(":", "/"))
+            .with_extension("data.json")
+    };
 
     if !hist.insert(template_path.clone()) {
         return ProcessResult {
@@ -35,6 +146,7 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
                 path: template_path,
                 message: Some(format!("{:?}", hist)),
             }],
+            assets: Assets::default(),
         };
     }
 
@@ -49,6 +161,7 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
             return ProcessResult {
                 output: String::new(),
                 errors: vec![e],
+                assets: Assets::default(),
             };
         }
     };
@@ -63,6 +176,7 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
         return ProcessResult {
             output: String::new(),
             errors,
+            assets: Assets::default(),
         };
     }
 
@@ -72,7 +186,9 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
         .join(name.replace(":", "/"))
         .with_extension("data.toml");
 
-    let v: Value = if toml_path.exists() {
+    let v: Value = if let Some(value) = inline {
+        value.clone()
+    } else if toml_path.exists() {
         // Use frontmatter-based loading
         match load_frontmatter_data(src, name) {
             Ok((value, fm_errors)) => {
@@ -84,6 +200,7 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
                 return ProcessResult {
                     output: String::new(),
                     errors,
+                    assets: Assets::default(),
                 };
             }
         }
@@ -100,6 +217,7 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
                 return ProcessResult {
                     output: String::new(),
                     errors: vec![e],
+                    assets: Assets::default(),
                 };
             }
         };
@@ -114,6 +232,7 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
             return ProcessResult {
                 output: String::new(),
                 errors,
+                assets: Assets::default(),
             };
         }
 
@@ -129,6 +248,7 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
                 return ProcessResult {
                     output: String::new(),
                     errors,
+                    assets: Assets::default(),
                 };
             }
         }
@@ -146,13 +266,21 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
             return ProcessResult {
                 output: String::new(),
                 errors,
+                assets: Assets::default(),
             };
         }
     };
 
+    if items.is_empty() {
+        return get_empty_state(src, name, params, hist, vars, errors);
+    }
+
     let mut contents = String::with_capacity(template.len() * items.len());
+    let count = items.len().to_string();
 
-    for object in items {
+    let entries = collection_entries(src, items);
+
+    for (index, object) in items.iter().enumerate() {
         let obj = match object.as_object() {
             Some(obj) => obj,
             None => {
@@ -169,7 +297,7 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
         let mut entry_path = String::new();
         let mut result_path = String::new();
         let mut is_entry = false;
-        let mut kv: Vec<(&str, &str)> = Vec::with_capacity(obj.len());
+        let mut kv: Vec<(&str, &str)> = Vec::with_capacity(obj.len() + params.len() + 5);
 
         for (key, value) in obj {
             let val = match value.as_str() {
@@ -202,30 +330,114 @@ pub fn get_template(src: &PathBuf, name: &str, mut hist: HashSet<PathBuf>) -> Pr
             kv.push((key, val));
         }
 
-        let processed_template = kv_replace(kv.clone(), template.clone());
+        // Loop metadata, available as ${--index}, ${--index1}, etc.
+        let index0 = index.to_string();
+        let index1 = (index + 1).to_string();
+        kv.push(("--index", &index0));
+        kv.push(("--index1", &index1));
+        kv.push(("--first", if index == 0 { "true" } else { "false" }));
+        kv.push((
+            "--last",
+            if index + 1 == items.len() {
+                "true"
+            } else {
+                "false"
+            },
+        ));
+        kv.push(("--count", &count));
+        kv.extend_from_slice(&params);
+
+        // Page-level variables are only visible to the listing, not to the entry pages
+        let mut item_kv = kv.clone();
+        item_kv.extend_from_slice(vars);
+        let processed_template = kv_replace(item_kv, template.clone());
         contents.push_str(&processed_template);
 
         if is_entry {
-            let entry_errs = process_entry(src, name, entry_path, result_path, kv);
+            let entry_errs = process_entry(src, name, entry_path, result_path, kv, &entries);
             errors.extend(entry_errs);
         }
     }
 
-    let page_res = page(src, contents, hist);
+    let page_res = page(src, contents, hist, vars);
+    errors.extend(page_res.errors);
+    ProcessResult {
+        output: page_res.output,
+        errors,
+        assets: page_res.assets,
+    }
+}
+
+/// Render the empty-state fallback (`Name.empty.html`) for a collection with no
+/// items. Renders nothing if the fallback doesn't exist.
+fn get_empty_state(
+    src: &PathBuf,
+    name: &str,
+    params: Vec<(&str, &str)>,
+    hist: HashSet<PathBuf>,
+    vars: &[(&str, &str)],
+    mut errors: Vec<ProcessError>,
+) -> ProcessResult {
+    let empty_path = src
+        .join("templates")
+        .join(name.replace(":", "/"))
+        .with_extension("empty.html");
+
+    if !empty_path.exists() {
+        return ProcessResult {
+            output: String::new(),
+            errors,
+            assets: Assets::default(),
+        };
+    }
+
+    let empty = match fs::read_to_string(&empty_path).map_proc_err(
+        WithItem::Template,
+        ErrorType::Io,
+        &empty_path,
+        Some("Failed to read empty-state file".to_string()),
+    ) {
+        Ok(content) => content,
+        Err(e) => {
+            errors.push(e);
+            return ProcessResult {
+                output: String::new(),
+                errors,
+                assets: Assets::default(),
+            };
+        }
+    };
+
+    let mut kv: Vec<(&str, &str)> = vec![("--count", "0")];
+    kv.extend(params.iter().chain(vars).copied());
+    let empty = kv_replace(kv, empty);
+    let page_res = page(src, empty, hist, vars);
     errors.extend(page_res.errors);
     ProcessResult {
         output: page_res.output,
         errors,
+        assets: page_res.assets,
     }
 }
 
-pub fn process_template(src: &PathBuf, input: String, hist: HashSet<PathBuf>) -> ProcessResult {
+pub fn process_template(
+    src: &PathBuf,
+    input: String,
+    hist: HashSet<PathBuf>,
+    inline_data: &InlineData,
+    vars: &[(&str, &str)],
+) -> ProcessResult {
     let mut errors = Vec::new();
+    let mut assets = Assets::default();
     let mut output = input;
 
     // Early return if no templates
     if !TEMPLATE_REGEX.is_match(&output).unwrap_or(false) {
-        return ProcessResult { output, errors };
+        return ProcessResult {
+            output,
+            errors,
+            assets: Assets::default(),
+        };
     }
 
     let mut replacements = Vec::new();
@@ -237,8 +449,8 @@ pub fn process_template(src: &PathBuf, input: String, hist: HashSet<PathBuf>) ->
                 let template_name = match found_str
                     .trim()
                     .strip_prefix("<::Template{")
-                    .and_then(|s| s.strip_suffix("/>"))
-                    .map(|s| s.trim().trim_end_matches('}'))
+                    .and_then(|s| s.split_once('}'))
+                    .map(|(name, _)| name.trim())
                 {
                     Some(name) if !name.is_empty() => name,
                     _ => {
@@ -255,8 +467,20 @@ pub fn process_template(src: &PathBuf, input: String, hist: HashSet<PathBuf>) ->
                     }
                 };
 
-                let result = get_template(src, template_name, hist.clone());
+                let params = get_targets_kv(template_name, found_str)
+                    .inspect_err(|e| errors.push((*e).clone()))
+                    .unwrap_or_default();
+
+                let result = get_template(
+                    src,
+                    template_name,
+                    params,
+                    inline_data.get(template_name),
+                    hist.clone(),
+                    vars,
+                );
                 errors.extend(result.errors);
+                assets.merge(result.assets);
                 replacements.push((found_str.to_owned(), result.output));
             }
             Err(e) => {
@@ -274,5 +498,105 @@ pub fn process_template(src: &PathBuf, input: String, hist: HashSet<PathBuf>) ->
         output = output.replacen(&old, &new, 1);
     }
 
-    ProcessResult { output, errors }
+    ProcessResult {
+        output,
+        errors,
+        assets,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::frontmatter::parse_page_frontmatter;
+    use crate::utils::fixture;
+    use serde_json::json;
+
+    fn render(src: &PathBuf, data: Value, params: Vec<(&str, &str)>) -> ProcessResult {
+        get_template(src, "Posts", params, Some(&data), HashSet::new(), &[])
+    }
+
+    #[test]
+    fn test_loop_variables() {
+        let src = fixture(
+            "loop",
+            &[(
+                "templates/Posts.template.html",
+                "${--index}/${--index1}/${--first}/${--last}/${--count}:${name};",
+            )],
+        );
+        let result = render(&src, json!([{ "name": "a" }, { "name": "b" }]), vec![]);
+        assert!(result.errors.is_empty());
+        assert_eq!(result.output, "0/1/true/false/2:a;1/2/false/true/2:b;");
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    #[test]
+    fn test_empty_state() {
+        let src = fixture(
+            "empty",
+            &[
+                ("templates/Posts.template.html", "${name}"),
+                ("templates/Posts.empty.html", "No ${kind} yet (${--count})"),
+            ],
+        );
+        let result = render(&src, json!([]), vec![("kind", "posts")]);
+        assert!(result.errors.is_empty());
+        assert_eq!(result.output, "No posts yet (0)");
+
+        // Without a fallback an empty collection renders nothing
+        fs::remove_file(src.join("templates/Posts.empty.html")).unwrap();
+        assert_eq!(render(&src, json!([]), vec![]).output, "");
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    #[test]
+    fn test_template_params() {
+        let src = fixture(
+            "params",
+            &[(
+                "templates/Posts.template.html",
+                "${data-x} ${col2} ${name} ${title};",
+            )],
+        );
+        let inline = InlineData::from([("Posts".to_string(), json!([{ "name": "a" }]))]);
+        let input = r#"<::Template{Posts} data-x="1" col2='b' name="p" />"#.to_string();
+
+        // Data fields win over parameters, and both over page variables
+        let result = process_template(
+            &src,
+            input,
+            HashSet::new(),
+            &inline,
+            &[("name", "v"), ("title", "Page")],
+        );
+        assert!(result.errors.is_empty());
+        assert_eq!(result.output, "1 b a Page;");
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    #[test]
+    fn test_inline_data() {
+        let input = concat!(
+            "<data name=\"Features\">\n- title: Fast\n- title: Small\n</data>",
+            "<data name=\"lower\">[]</data><::Template{Features} />",
+        );
+        let (result, data) = extract_inline_data(input.to_string());
+        assert_eq!(
+            result.output,
+            "<data name=\"lower\">[]</data><::Template{Features} />"
+        );
+        assert_eq!(
+            data["Features"],
+            json!([{ "title": "Fast" }, { "title": "Small" }])
+        );
+
+        // Lists in a page's frontmatter arrive as the same blocks
+        let page = "---\ntitle: Home\nFeatures:\n  - title: Fast\nnotes: [1]\n---\nBody";
+        let fm = parse_page_frontmatter(page, &PathBuf::from("index.html")).unwrap();
+        assert_eq!(fm.warnings.len(), 1);
+        let blocks: String = fm.data.iter().map(|(k, v)| data_block(k, v)).collect();
+        let (_, data) = extract_inline_data(blocks);
+        assert_eq!(data["Features"], json!([{ "title": "Fast" }]));
+    }
 }
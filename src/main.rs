@@ -1,17 +1,41 @@
 mod handlers {
+    pub mod assets;
+    pub mod callouts;
+    pub mod check;
+    pub mod code_blocks;
     pub mod components;
     pub mod entries;
+    pub mod feeds;
+    pub mod fingerprint;
     pub mod frontmatter;
+    pub mod highlight;
+    pub mod images;
     pub mod katex_assets;
+    pub mod layouts;
     pub mod markdown;
+    pub mod minify;
+    pub mod outputs;
     pub mod pages;
+    pub mod refs;
+    pub mod search;
+    pub mod sitemap;
     pub mod templates;
 }
+mod config;
+mod dates;
 mod dev;
 mod error;
 mod new;
 mod utils;
+use crate::handlers::check::check_links;
+use crate::handlers::feeds::write_feeds;
+use crate::handlers::fingerprint::fingerprint_assets;
+use crate::handlers::minify::minify_assets;
+use crate::handlers::outputs;
 use crate::handlers::pages::process_pages;
+use crate::handlers::search::write_search_index;
+use crate::handlers::sitemap::write_sitemap;
+use crate::handlers::{highlight, images, katex_assets};
 use color_print::{cformat, cprintln};
 use dev::spawn_watcher;
 use error::{ErrorType, MapProcErr, ProcessError, WithItem};
@@ -32,6 +56,18 @@ fn main() {
         return;
     }
 
+    // Prints a stylesheet, so it takes options instead of a project folder
+    if args.len() > 1 && args[1] == "highlight-css" {
+        match highlight::highlight_css_command(&args) {
+            Ok(css) => print!("{}", css),
+            Err(e) => {
+                eprintln!("{}", cformat!("<s><r>Error</></>: {e}"));
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
     if args.len() < 3 {
         eprintln!(
             "{}",
@@ -44,10 +80,12 @@ fn main() {
     match command {
         "dev" => {
             let _ = IS_DEV.set(true);
+            load_config(&args);
             spawn_watcher(args);
         }
-        "build" => {
+        "build" | "check" => {
             let _ = IS_DEV.set(false);
+            load_config(&args);
             if let Err(errors) = build(args) {
                 print_vec_errs(&errors);
                 process::exit(1);
@@ -59,11 +97,18 @@ fn main() {
             });
         }
         _ => {
-            println!("Unknown operation. Operations: build, dev, new");
+            println!("Unknown operation. Operations: build, check, dev, new, highlight-css");
         }
     }
 }
 
+fn load_config(args: &[String]) {
+    if let Err(e) = config::load(&PathBuf::from(&args[2])) {
+        eprintln!("{}", cformat!("<s><r>Config error</></>: {e}"));
+        process::exit(1);
+    }
+}
+
 fn build(args: Vec<String>) -> Result<(), Vec<ProcessError>> {
     cprintln!("<c><s>Building</></>...");
     let mut errors: Vec<ProcessError> = Vec::new();
@@ -100,14 +145,63 @@ fn build(args: Vec<String>) -> Result<(), Vec<ProcessError>> {
         }
     }
 
+    outputs::reset();
+    katex_assets::reset_build_flag();
+    images::reset();
+
     if let Err(mut page_errors) = process_pages(&dir, &src, src.clone(), pages) {
         errors.append(&mut page_errors);
     }
 
+    // Before public is copied, so files of its own in the images folder stay
+    if errors.is_empty() {
+        if let Err(e) = images::prune(&dir, &dist) {
+            errors.push(e);
+        }
+    }
+
     if let Err(e) = utils::copy_into(&public, &dist) {
         errors.push(e);
     }
 
+    if let Err(e) = katex_assets::copy_katex_assets(&dir, &dist) {
+        errors.push(e);
+    }
+
+    if let Err(e) = highlight::write_highlight_css(&dir, &dist) {
+        errors.push(e);
+    }
+
+    // Hashed names would pile up in the dev output, and nothing caches it.
+    // Assets are minified first so the hashes cover the files that ship.
+    if !*IS_DEV.get().unwrap_or(&false) {
+        if let Err(mut minify_errors) = minify_assets(&public, &dist) {
+            errors.append(&mut minify_errors);
+        }
+        if let Err(mut fingerprint_errors) = fingerprint_assets(&public, &dist) {
+            errors.append(&mut fingerprint_errors);
+        }
+    }
+
+    if let Err(e) = write_sitemap(&dist) {
+        errors.push(e);
+    }
+
+    if let Err(mut feed_errors) = write_feeds(&src, &dist) {
+        errors.append(&mut feed_errors);
+    }
+
+    if let Err(mut search_errors) = write_search_index(&dist) {
+        errors.append(&mut search_errors);
+    }
+
+    // Links are checked on request, never in dev mode
+    if !*IS_DEV.get().unwrap_or(&false) && (args[1] == "check" || config::get().check.enabled) {
+        if let Err(mut check_errors) = check_links(&dist) {
+            errors.append(&mut check_errors);
+        }
+    }
+
     let duration = Instant::now().duration_since(start).as_millis();
 
     if errors.is_empty() {
@@ -1,16 +1,19 @@
+use crate::config;
 use crate::error::ErrorType::Io;
 use crate::error::{ErrorType, MapProcErr, ProcessError, WithItem};
+use crate::handlers::assets::Assets;
+use crate::handlers::refs::{html_refs, raw_text_ranges, replace_ranges};
 use color_print::cformat;
 use fancy_regex::Regex;
 use once_cell::sync::Lazy;
 use std::collections::HashSet;
 use std::fs;
 use std::net::TcpListener;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use WithItem::File;
 
 static KV_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r#"(\w+)=(['"])(?:(?!\2).)*\2"#)
+    Regex::new(r#"([A-Za-z][\w-]*)=(['"])(?:(?!\2).)*\2"#)
         .expect("Regex failed to parse, this shouldn't happen")
 });
 
@@ -19,8 +22,17 @@ static PLACEHOLDER_REGEX: Lazy<Regex> = Lazy::new(|| {
         .expect("Regex failed to parse, this shouldn't happen")
 });
 
+/// `<pre>` and `<code>` elements and inline markdown code spans.
+static CODE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?is)<(pre|code)\b[^>]*>.*?</\1\s*>|(`+)[^\n]*?\2")
+        .expect("Regex failed to parse, this shouldn't happen")
+});
+
 const VAR_OPEN_PLACEHOLDER: &str = "\x00simple_var_open\x00";
 
+const BASE_VAR: &str = "${--base}";
+const BASE_PLACEHOLDER: &str = "\x00simple_base\x00";
+
 /// Replace tokens only inside fenced code blocks (``` / ~~~), leaving text
 /// outside fences untouched.
 pub fn shield_fenced_code_with_replacements(input: &str, replacements: &[(&str, &str)]) -> String {
@@ -154,6 +166,19 @@ pub fn kv_replace(kv: Vec<(&str, &str)>, from: String) -> String {
     result.replace(VAR_OPEN_PLACEHOLDER, "${")
 }
 
+/// [`kv_replace`] over a whole page, leaving placeholders inside scripts and
+/// styles alone since `${...}` there is JS or CSS.
+pub fn kv_replace_markup(kv: Vec<(&str, &str)>, from: String) -> String {
+    let shielded = raw_text_ranges(&from)
+        .into_iter()
+        .map(|range| {
+            let text = from[range.clone()].replace("${", VAR_OPEN_PLACEHOLDER);
+            (range, text)
+        })
+        .collect();
+    kv_replace(kv, replace_ranges(&from, shielded))
+}
+
 pub fn get_inside(input: String, from: &str, to: &str) -> Option<String> {
     let start_index = input.find(from)?;
     let start_pos = start_index + from.len();
@@ -200,6 +225,187 @@ pub fn copy_into(public: &PathBuf, dist: &PathBuf) -> Result<(), ProcessError> {
     Ok(())
 }
 
+/// With `pretty_urls` enabled, `about.html` becomes `about/index.html`.
+/// Index and 404 pages are left as they are.
+pub fn pretty_path(path: PathBuf) -> PathBuf {
+    pretty_path_with(path, config::get().pretty_urls)
+}
+
+fn pretty_path_with(path: PathBuf, pretty_urls: bool) -> PathBuf {
+    if !pretty_urls || path.extension().and_then(|s| s.to_str()) != Some("html") {
+        return path;
+    }
+
+    match path.file_stem().and_then(|s| s.to_str()) {
+        Some("index") | Some("404") | None => path,
+        Some(stem) => path.with_file_name(stem).join("index.html"),
+    }
+}
+
+/// Link to the page written at `result_path` (relative to the output directory).
+/// Pretty URLs and sites with a base path are linked from the site root, so
+/// they work from any page.
+pub fn page_link(result_path: &str) -> String {
+    let config = config::get();
+    page_link_with(result_path, config.pretty_urls, &config.base)
+}
+
+fn page_link_with(result_path: &str, pretty_urls: bool, base: &str) -> String {
+    if !pretty_urls && base == "/" {
+        return format!("./{}", result_path);
+    }
+
+    let pretty = pretty_path_with(
+        PathBuf::from(result_path.trim_start_matches('/')),
+        pretty_urls,
+    );
+    let pretty = pretty.to_string_lossy().replace('\\', "/");
+    format!(
+        "{}{}",
+        base,
+        pretty.strip_suffix("index.html").unwrap_or(&pretty)
+    )
+}
+
+/// Prefix root-relative URLs in `href`, `src` and `srcset` attributes and CSS
+/// `url()`s with the configured base path.
+pub fn apply_base(html: String) -> String {
+    let base = &config::get().base;
+    if base == "/" {
+        return html;
+    }
+    prefix_base(html, base)
+}
+
+fn prefix_base(html: String, base: &str) -> String {
+    let bare = base.trim_end_matches('/');
+    let replacements = html_refs(&html)
+        .into_iter()
+        .filter(|range| {
+            let path = &html[range.clone()];
+            path.starts_with('/') && !path.starts_with("//")
+        })
+        // Already prefixed, e.g. through ${--base} or a generated link
+        .filter(|range| {
+            let path = &html[range.clone()];
+            !path.starts_with(base) && path.split(['?', '#']).next() != Some(bare)
+        })
+        .map(|range| {
+            let prefixed = format!("{}{}", base, &html[range.start + 1..range.end]);
+            (range, prefixed)
+        })
+        .collect();
+    replace_ranges(&html, replacements)
+}
+
+/// Fill in `${--base}`, except in code (fenced blocks, inline code spans and
+/// `<pre>`/`<code>` elements) so pages can document the syntax itself. Scripts
+/// and styles are always filled in. Runs before markdown is rendered, while
+/// code is still recognizable.
+pub fn fill_base(input: String) -> String {
+    if !input.contains(BASE_VAR) {
+        return input;
+    }
+    fill_base_with(&input, &config::get().base)
+}
+
+fn fill_base_with(input: &str, base: &str) -> String {
+    let shielded = shield_fenced_code_with_replacements(input, &[(BASE_VAR, BASE_PLACEHOLDER)]);
+    let mut result = String::with_capacity(shielded.len());
+    let mut last_end = 0;
+    // Scripts and styles aren't markup, so backticks there aren't code spans
+    for raw in raw_text_ranges(&shielded) {
+        result.push_str(&fill_markup(&shielded[last_end..raw.start], base));
+        result.push_str(&shielded[raw.clone()].replace(BASE_VAR, base));
+        last_end = raw.end;
+    }
+    result.push_str(&fill_markup(&shielded[last_end..], base));
+    result.replace(BASE_PLACEHOLDER, BASE_VAR)
+}
+
+fn fill_markup(markup: &str, base: &str) -> String {
+    let mut result = String::with_capacity(markup.len());
+    let mut last_end = 0;
+    for code in CODE_REGEX.find_iter(markup).filter_map(|m| m.ok()) {
+        result.push_str(&markup[last_end..code.start()].replace(BASE_VAR, base));
+        result.push_str(code.as_str());
+        last_end = code.end();
+    }
+    result.push_str(&markup[last_end..].replace(BASE_VAR, base));
+    result
+}
+
+/// Decode `%XX` escapes, like the `%20` markdown writes for spaces.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Resolve `.` and `..` in `path` without touching the file system. Leading
+/// `..` that can't be resolved are kept.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// 64-bit FNV-1a hash of `bytes`. Used to name generated files after their
+/// content, so it needs to be stable across builds and platforms.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Escape text for use in XML content and attribute values.
+pub fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
 pub fn unindent(input: &str) -> String {
     let lines: Vec<&str> = input.lines().collect();
 
@@ -235,6 +441,7 @@ pub fn unindent(input: &str) -> String {
 pub struct ProcessResult {
     pub output: String,
     pub errors: Vec<ProcessError>,
+    pub assets: Assets,
 }
 
 pub fn print_vec_errs(errors: &Vec<ProcessError>) {
@@ -291,6 +498,20 @@ fn is_port_available(port: u16) -> bool {
     TcpListener::bind(("0.0.0.0", port)).is_ok()
 }
 
+/// A folder under the system temp dir holding `files`, for tests that need a
+/// project on disk.
+#[cfg(test)]
+pub fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("simple-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -326,6 +547,24 @@ mod tests {
         assert_eq!(result, "Outside: red\n```html\n<div>${color}</div>\n```");
     }
 
+    #[test]
+    fn test_kv_replace_markup() {
+        let kv = vec![("title", "Home")];
+        let from = concat!(
+            "<h1 title=\"${title}\">${title}</h1>",
+            "<script>const t = `${title} ${count}`;</script>",
+            "<style>.a::after { content: \"${title}\" }</style>",
+        );
+        assert_eq!(
+            kv_replace_markup(kv, from.to_string()),
+            concat!(
+                "<h1 title=\"Home\">Home</h1>",
+                "<script>const t = `${title} ${count}`;</script>",
+                "<style>.a::after { content: \"${title}\" }</style>",
+            )
+        );
+    }
+
     #[test]
     fn test_unindent() {
         let input = "
@@ -345,4 +584,106 @@ You
         let result = unindent(input);
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_pretty_path() {
+        let pretty = |path: &str| pretty_path_with(PathBuf::from(path), true);
+        assert_eq!(pretty("about.html"), PathBuf::from("about/index.html"));
+        assert_eq!(
+            pretty("blog/post.html"),
+            PathBuf::from("blog/post/index.html")
+        );
+        assert_eq!(pretty("index.html"), PathBuf::from("index.html"));
+        assert_eq!(pretty("404.html"), PathBuf::from("404.html"));
+        assert_eq!(pretty("feed.xml"), PathBuf::from("feed.xml"));
+        assert_eq!(
+            pretty_path_with(PathBuf::from("about.html"), false),
+            PathBuf::from("about.html")
+        );
+    }
+
+    #[test]
+    fn test_page_link() {
+        let result_path = "content/post.html";
+        assert_eq!(
+            page_link_with(result_path, false, "/"),
+            "./content/post.html"
+        );
+        assert_eq!(page_link_with(result_path, true, "/"), "/content/post/");
+        assert_eq!(
+            page_link_with(result_path, false, "/docs/"),
+            "/docs/content/post.html"
+        );
+        assert_eq!(
+            page_link_with(result_path, true, "/docs/"),
+            "/docs/content/post/"
+        );
+        assert_eq!(page_link_with("index.html", true, "/docs/"), "/docs/");
+    }
+
+    #[test]
+    fn test_prefix_base() {
+        let html = concat!(
+            r#"<a href="/about/">About</a><a href="/docs">Home</a><a href="/docs/x">X</a>"#,
+            r#"<a href="//cdn.example.org/a.js">CDN</a><a href="about/">Rel</a>"#,
+            r#"<img src="/a.png" srcset="/a-480.png 480w, /a.png 960w">"#,
+            r#"<div style="background: url('/bg.png')"></div>"#,
+            r#"<style>.x { background: url(/img/x.png) }</style>"#,
+        );
+        assert_eq!(
+            prefix_base(html.to_string(), "/docs/"),
+            concat!(
+                r#"<a href="/docs/about/">About</a><a href="/docs">Home</a><a href="/docs/x">X</a>"#,
+                r#"<a href="//cdn.example.org/a.js">CDN</a><a href="about/">Rel</a>"#,
+                r#"<img src="/docs/a.png" srcset="/docs/a-480.png 480w, /docs/a.png 960w">"#,
+                r#"<div style="background: url('/docs/bg.png')"></div>"#,
+                r#"<style>.x { background: url(/docs/img/x.png) }</style>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_fill_base() {
+        let input = concat!(
+            "<a href=\"${--base}about/\">About</a> uses `${--base}`\n",
+            "<pre><code>&lt;img src=\"${--base}logo.png\"&gt;</code></pre>\n",
+            "```html\n<img src=\"${--base}logo.png\">\n```\n",
+            "<script>fetch(`${--base}search.json`); const code = `<code>`;</script>\n",
+            "<style>.a { background: url(\"${--base}a.png\") }</style>\n",
+        );
+        assert_eq!(
+            fill_base_with(input, "/docs/"),
+            concat!(
+                "<a href=\"/docs/about/\">About</a> uses `${--base}`\n",
+                "<pre><code>&lt;img src=\"${--base}logo.png\"&gt;</code></pre>\n",
+                "```html\n<img src=\"${--base}logo.png\">\n```\n",
+                "<script>fetch(`/docs/search.json`); const code = `<code>`;</script>\n",
+                "<style>.a { background: url(\"/docs/a.png\") }</style>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path(Path::new("src/data/posts/../../public/./a.png")),
+            PathBuf::from("src/public/a.png")
+        );
+        assert_eq!(
+            normalize_path(Path::new("../site/src/../x")),
+            PathBuf::from("../site/x")
+        );
+        assert_eq!(
+            normalize_path(Path::new("a/../../b")),
+            PathBuf::from("../b")
+        );
+        assert_eq!(normalize_path(Path::new("/../a")), PathBuf::from("/a"));
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
 }